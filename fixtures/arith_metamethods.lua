@@ -0,0 +1,42 @@
+local Vector = {}
+Vector.__index = Vector
+
+local function vector(x, y)
+    return setmetatable({x = x, y = y}, Vector)
+end
+
+Vector.__add = function(a, b) return vector(a.x + b.x, a.y + b.y) end
+Vector.__sub = function(a, b) return vector(a.x - b.x, a.y - b.y) end
+Vector.__mul = function(a, b)
+    if type(a) == "number" then
+        return vector(a * b.x, a * b.y)
+    end
+    return vector(a.x * b, a.y * b)
+end
+Vector.__div = function(a, b) return vector(a.x / b, a.y / b) end
+Vector.__idiv = function(a, b) return vector(a.x // b, a.y // b) end
+Vector.__mod = function(a, b) return vector(a.x % b, a.y % b) end
+Vector.__pow = function(a, b) return "pow" end
+Vector.__unm = function(a, b) return vector(-a.x, -a.y) end
+
+local function show(v)
+    return v.x .. "," .. v.y
+end
+
+local a, b = vector(1, 2), vector(10, 20)
+print(show(a + b), show(b - a), show(a * 3), show(3 * a), show(b / 4), show(b // 3), show(b % 3), a ^ 2, show(-a))
+
+-- bitwise metamethods, the left operand wins if both have one
+local Bits = {}
+local function bits(v) return setmetatable({v = v}, Bits) end
+local Other = {__band = function() return "other" end}
+Bits.__band = function(a, b) return "band" end
+Bits.__bor = function(a, b) return "bor" end
+Bits.__bxor = function(a, b) return "bxor" end
+Bits.__shl = function(a, b) return "shl " .. b end
+Bits.__shr = function(a, b) return "shr " .. a end
+Bits.__bnot = function(a, b) return "bnot " .. (a == b and "same" or "different") end
+
+local x = bits(1)
+local y = setmetatable({}, Other)
+print(x & 1, x | 1, x ~ 1, x << 2, 3 >> x, ~x, x & y, y & x, 1.5 & x)
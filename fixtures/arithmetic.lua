@@ -0,0 +1,12 @@
+local max, min = 9223372036854775807, -9223372036854775807 - 1
+local three, two = 3, 2
+
+print(max + 1 == min, min - 1 == max, max * 2)
+print(7 / 2, 4 / two, 2 ^ 10, 2 ^ 0.5)
+print(7 // 2, -7 // 2, 7 // -2, -7.5 // 2, 7 // 0.0)
+print(5 % three, -5 % three, 5 % -three, -5.5 % 2, 5.5 % -2)
+print(min // -1, min % -1)
+print(-1 >> 1, 1 << 63, 1 << 64, -1 << -1, 8 >> -1)
+print(3.0 | 0, ~5.0, -three, -(2.5))
+print("10" + 1, "0x10" * 2, " 1.5 " + 1, "1e2" - 0, 10 .. "")
+print(1e15, 1e100, 0.1, -0.0, 1/0, -1/0, 100 / 3)
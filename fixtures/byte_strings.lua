@@ -0,0 +1,32 @@
+local packed = "\xff\x00\x01\x7f"
+print(#packed, #"\0", #"\u{e4}")
+print(packed == "\xff\0\1\127", packed ~= "\xff\0\1\128")
+
+-- comparisons are byte-wise
+print("\xff" > "a", "a\0b" < "a\0c", "a" < "a\0", "\xc3\xa4" > "z")
+
+-- concatenation keeps every byte
+local joined = packed .. "\0" .. 1 .. "\xfe"
+print(#joined, joined == "\xff\0\1\127\0001\xfe")
+
+-- binary strings are distinct table keys
+local t = {}
+t["\xff"] = "ff"
+t["\xfe"] = "fe"
+t["\0"] = "nul"
+t[""] = "empty"
+print(t["\xff"], t["\xfe"], t["\0"], t[""], t["\xff" .. ""])
+
+local count = 0
+for k, v in pairs(t) do
+    count = count + 1
+end
+print(count)
+
+print("valid \u{48}\u{e9}llo")
+print(tostring("\xe2\x82\xac"), type("\xff"))
+
+-- raw bytes survive concatenation and a round trip through a table key
+local stored = {}
+stored["\xff" .. "\0"] = "\xff\0" .. "\xfe"
+print(stored["\xff\0"], "\xff" .. 1)
@@ -0,0 +1,17 @@
+local big = 9007199254740993
+local nan = 0 / 0
+print(big == 2^53, big > 2^53, 2^53 < big, 1 == 1.0, -0.0 == 0, nan == nan, nan < 1, 1 <= nan)
+print(math_huge == nil, 9223372036854775807 < 2^63, -9223372036854775807 - 1 == -2^63, 3 < 3.5, 4 <= 3.5)
+
+print("a" < "b", "abc" < "abd", "Z" < "a", "" < "a", "a\0b" < "a\0c", "10" < "9", "x" <= "x")
+
+local Set = {}
+local function set(n) return setmetatable({n = n}, Set) end
+Set.__eq = function(a, b) return a.n == b.n end
+Set.__lt = function(a, b) return a.n < b.n end
+local one, other_one, two = set(1), set(1), set(2)
+print(one == other_one, one ~= two, one < two, two < one, one <= other_one, two <= one)
+
+local Le = {__le = function(a, b) return "yes" end}
+local p, q = setmetatable({}, Le), setmetatable({}, Le)
+print(p <= q, p == q, p == p, one == 1)
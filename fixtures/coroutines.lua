@@ -0,0 +1,155 @@
+-- generators
+local function range(n)
+  return coroutine.wrap(function()
+    for i = 1, n do
+      coroutine.yield(i)
+    end
+  end)
+end
+local sum = 0
+for i in range(5) do
+  sum = sum + i
+end
+print("sum", sum)
+
+-- passing values in and out
+local co = coroutine.create(function(a, b)
+  print("start", a, b)
+  local c = coroutine.yield(a + b)
+  print("got", c)
+  local d, e = coroutine.yield(c * 2)
+  print("got", d, e)
+  return "done", d + e
+end)
+print(type(co), coroutine.status(co))
+print(coroutine.resume(co, 1, 2))
+print(coroutine.status(co))
+print(coroutine.resume(co, 10))
+print(coroutine.resume(co, 3, 4))
+print(coroutine.status(co))
+print(coroutine.resume(co))
+
+-- errors go back to resume
+local failing = coroutine.create(function()
+  coroutine.yield(1)
+  error("oops")
+end)
+print(coroutine.resume(failing))
+print(coroutine.resume(failing))
+print(coroutine.status(failing))
+local ok, err = coroutine.resume(coroutine.create(function() error({code = 42}) end))
+print(ok, err.code)
+local ok, err = coroutine.resume(coroutine.create(function() local t = nil; return t.x end))
+print(ok, err)
+
+-- status from the inside, running and isyieldable
+local outer
+outer = coroutine.create(function()
+  local inner = coroutine.create(function()
+    print("outer is", coroutine.status(outer))
+    print("inner is", coroutine.status(coroutine.running()))
+    print("yieldable", coroutine.isyieldable())
+  end)
+  coroutine.resume(inner)
+  print("inner is", coroutine.status(inner))
+  print(coroutine.resume(outer))
+end)
+coroutine.resume(outer)
+local main, ismain = coroutine.running()
+print(type(main), ismain, coroutine.isyieldable())
+
+-- yield across pcall and from nested Lua calls
+local function deep(n)
+  if n == 0 then
+    return coroutine.yield("bottom")
+  end
+  return (deep(n - 1)) .. "+"
+end
+local pco = coroutine.create(function()
+  local ok, v = pcall(function()
+    local x = coroutine.yield("in pcall")
+    local y = deep(3)
+    error("after " .. x .. " " .. y)
+  end)
+  print("pcall caught", ok, v)
+  print(xpcall(function()
+    coroutine.yield("in xpcall")
+    error("again")
+  end, function(m) return "handled: " .. m end))
+  print(pcall(coroutine.yield, "direct"))
+  print(select("#", pcall(pcall, coroutine.yield, "nested")))
+  return "finished"
+end)
+print(coroutine.resume(pco))
+print(coroutine.resume(pco, "x"))
+print(coroutine.resume(pco, "y"))
+print(coroutine.resume(pco))
+print(coroutine.resume(pco, "a", "b"))
+print(coroutine.resume(pco, "c"))
+print(coroutine.status(pco))
+
+-- upvalues shared between threads
+local counter = 0
+local function bump() counter = counter + 1; return counter end
+local uco = coroutine.wrap(function()
+  local own = 100
+  local get = function() return own end
+  coroutine.yield(get)
+  own = own + bump()
+  coroutine.yield(own)
+end)
+local get = uco()
+print("own", get())
+print("own", uco(), get(), counter)
+
+-- yielding through a tail call and a generic for
+local tco = coroutine.wrap(function()
+  for k, v in function(_, k) local n = (k or 0) + 1; if n <= 2 then return n, coroutine.yield(n) end end do
+    print("iter", k, v)
+  end
+  return coroutine.yield("tail")
+end)
+print(tco())
+print(tco("v1"))
+print(tco("v2"))
+print(tco("last"))
+
+-- misuse
+print(pcall(coroutine.yield, 1))
+print(coroutine.resume(coroutine.running()))
+local dead = coroutine.wrap(function() end)
+dead()
+print(pcall(dead))
+print(pcall(function() dead() end))
+print(pcall(coroutine.create, 1))
+print(pcall(coroutine.resume, {}))
+print(coroutine.resume(coroutine.create(function() return tostring(setmetatable({}, {__tostring = function() coroutine.yield() end})) end)))
+print(pcall(coroutine.wrap(function() error("wrapped") end)))
+print(coroutine.resume(coroutine.create(print), "native", "body"))
+local yco = coroutine.create(coroutine.yield)
+print(coroutine.resume(yco, 1, 2))
+print(coroutine.resume(yco, 3))
+print(coroutine.status(yco))
+
+-- suspended coroutines in reference cycles are collected
+collectgarbage()
+local before = collectgarbage("count")
+for i = 1, 2000 do
+  local t = {}
+  t.co = coroutine.create(function() local keep = t; coroutine.yield(keep) end)
+  coroutine.resume(t.co)
+end
+collectgarbage()
+print(collectgarbage("count") - before < 100)
+local weak = setmetatable({}, {__mode = "k"})
+weak[coroutine.create(function() end)] = true
+collectgarbage()
+print(next(weak))
+-- including the ones behind coroutine.wrap
+do
+  local gen
+  gen = coroutine.wrap(function() while true do coroutine.yield(gen) end end)
+  weak[gen()] = true
+end
+collectgarbage()
+print(next(weak))
@@ -0,0 +1,39 @@
+print(pcall(error, "msg"))
+print(pcall(error, "msg", 0))
+print(pcall(error, "msg", 2))
+print(pcall(error))
+print(pcall(error, 42))
+local t = {}
+local ok, err = pcall(error, t)
+print(ok, err == t)
+
+local function f() error("in f") end
+print(pcall(f))
+local function g() error("in g", 2) end
+local function h() g() end
+print(pcall(h))
+print(pcall(function(...) return ... end, 1, 2, 3))
+
+-- nested protected calls restore the stack
+local function nested(n)
+    if n == 0 then error("bottom") end
+    local ok, err = pcall(nested, n - 1)
+    return n .. ":" .. tostring(ok) .. ":" .. err
+end
+print(pcall(nested, 3))
+
+-- the message handler runs before unwinding, so it can still see the failing function
+local function inner() error("deep") end
+local function handler(m)
+    local _, where = pcall(error, "", 4)
+    return m .. " (handled at '" .. where .. "')"
+end
+print(xpcall(function() inner() end, handler))
+print(xpcall(error, function(m) error("again") end, "boom"))
+print(xpcall(function(a, b) return a + b end, print, 1, 2))
+
+-- errors inside the handler's protected region don't reach outer handlers twice
+print(xpcall(function()
+    local ok, err = pcall(error, "caught")
+    error(err .. " and rethrown")
+end, function(m) return "handled: " .. m end))
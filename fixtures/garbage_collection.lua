@@ -0,0 +1,28 @@
+collectgarbage("stop")
+local before = collectgarbage("count")
+for i = 1, 1000 do
+    local t = {}
+    t.self = t
+    local captured = {}
+    captured.f = function() return captured end
+end
+local peak = collectgarbage("count")
+print(collectgarbage("isrunning"), peak > before)
+collectgarbage("restart")
+print(collectgarbage("collect"), collectgarbage("count") < peak)
+
+-- reachable cycles survive
+local keep = {}
+keep.f = function() return keep end
+keep.self = keep
+collectgarbage()
+print(keep.f() == keep, keep.self == keep, collectgarbage("step"), collectgarbage("isrunning"))
+
+-- steps only complete a cycle once they add up to the memory in use
+local live = {}
+for i = 1, 100 do
+    live[i] = {}
+end
+collectgarbage()
+print(collectgarbage("step", 1), collectgarbage("step", 1 << 20))
+print(pcall(collectgarbage, "step", 1.5))
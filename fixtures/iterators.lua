@@ -0,0 +1,45 @@
+local list = {}
+list[1] = "a"
+list[2] = "b"
+list[3] = "c"
+for i, v in ipairs(list) do
+    print(i .. " " .. v)
+end
+
+local record = {}
+record.x = 1
+record.y = 2
+record.z = 3
+local sum, count = 0, 0
+for k, v in pairs(record) do
+    sum = sum + v
+    count = count + 1
+    record[k] = v * 10 -- assigning existing keys during traversal
+end
+assert(sum == 6)
+assert(count == 3)
+assert(record.x == 10 and record.y == 20 and record.z == 30)
+
+local key, value = next(record)
+assert(record[key] == value)
+assert(next({}) == nil)
+
+local function range(n)
+    local function step(limit, i)
+        if i < limit then
+            return i + 1
+        end
+    end
+    return step, n, 0
+end
+
+local total = 0
+for i in range(4) do
+    total = total + i
+end
+print(total)
+
+for k, v in pairs(list) do
+    list[k] = nil -- clearing fields during traversal
+end
+assert(next(list) == nil)
@@ -0,0 +1,17 @@
+local empty, seq, holes = {}, {1, 2, 3, 4, 5}, {}
+for i = 1, 1000 do
+    holes[i] = i
+end
+holes[1001] = nil
+holes[1003] = true
+
+local grown = {}
+for i = 1, 100 do
+    grown[#grown + 1] = i * i
+end
+
+local shrunk = {1, 2, 3}
+shrunk[3] = nil
+
+print(#empty, #seq, #holes, #grown, grown[#grown], #shrunk, #"hello", #"")
+print(#"\xff\xfe", #"\xff\0\xfe", #"\u{e4}")
@@ -0,0 +1,54 @@
+-- classes
+local Point = {}
+Point.__index = Point
+
+function Point.new(x, y)
+    return setmetatable({x = x, y = y}, Point)
+end
+
+function Point:length2()
+    return self.x * self.x + self.y * self.y
+end
+
+local p = Point.new(3, 4)
+print(p:length2(), getmetatable(p) == Point)
+
+-- inheritance through a chain of __index tables
+local Base = {greet = function() return "hello" end}
+local Middle = setmetatable({}, {__index = Base})
+local Leaf = setmetatable({}, {__index = Middle})
+print(Leaf.greet(), Leaf.missing)
+
+-- default values from an __index function
+local defaults = setmetatable({}, {__index = function(t, k) return k .. "?" end})
+defaults.set = "set"
+print(defaults.set, defaults.unset)
+
+-- proxies with __newindex
+local log = {}
+local store = {}
+local proxy = setmetatable({}, {
+    __index = store,
+    __newindex = function(t, k, v)
+        log[#log + 1] = k
+        store[k] = v
+    end,
+})
+proxy.a = 1
+proxy.b = 2
+proxy.a = 3
+print(proxy.a, proxy.b, #log, log[3], next(proxy))
+
+-- __newindex table redirects the assignment
+local target = {}
+local redirect = setmetatable({}, {__newindex = target})
+redirect.x = 10
+print(redirect.x, target.x)
+
+-- globals go through the same path
+setmetatable(_ENV, {__index = function(_, name) return "global " .. name end})
+print(undefined_variable)
+
+-- protected metatables
+local locked = setmetatable({}, {__metatable = "locked"})
+print(getmetatable(locked))
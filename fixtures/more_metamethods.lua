@@ -0,0 +1,42 @@
+-- __call
+local calls = 0
+local memo = setmetatable({}, {__call = function(self, n)
+    calls = calls + 1
+    local cached = self[n]
+    if cached == nil then
+        cached = n * n
+        self[n] = cached
+    end
+    return cached
+end})
+print(memo(4), memo(4), memo(5), calls)
+
+local Class = setmetatable({}, {__call = function(cls, ...) return select("#", ...), ... end})
+print(Class(1, 2, 3))
+
+local function tail(x) return memo(x) end
+print(tail(6))
+
+-- callable tables called with a variable amount of arguments
+print(Class(select(1, "a", "b")))
+
+-- __concat
+local Path = {}
+Path.__concat = function(a, b)
+    local left = type(a) == "table" and a.path or a
+    local right = type(b) == "table" and b.path or b
+    return setmetatable({path = left .. "/" .. right}, Path)
+end
+local root = setmetatable({path = "root"}, Path)
+print((root .. "usr" .. "bin").path, ("home" .. root).path, "a" .. 1 .. "b" .. 2.5)
+
+-- __len
+local sized = setmetatable({1, 2, 3}, {__len = function() return 42 end})
+print(#sized, #{1, 2})
+
+-- __tostring and __name
+local Named = setmetatable({}, {__tostring = function(t) return "I am named" end})
+print(Named, tostring(Named), tostring(nil), tostring(1.5), tostring(true))
+local typed = setmetatable({}, {__name = "MyType"})
+print(typed)
+print({}, print)
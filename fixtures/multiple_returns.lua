@@ -0,0 +1,41 @@
+local function three()
+    return 1, 2, 3
+end
+
+local function none()
+end
+
+local function forward(...)
+    return ...
+end
+
+local function count(...)
+    local n = select('#', ...)
+    return n
+end
+
+local function wrap()
+    return three()
+end
+
+local function prefixed()
+    return 0, three()
+end
+
+print(three())
+print(forward(three()))
+print(prefixed())
+print(wrap())
+assert(count(three()) == 3)
+assert(count(three(), three()) == 4)
+assert(count(none()) == 0)
+assert(count(forward()) == 0)
+
+local a, b, c, d = three()
+assert(a == 1 and b == 2 and c == 3 and d == nil)
+local x, y = none()
+assert(x == nil and y == nil)
+
+local t = {three(), three()}
+assert(t[1] == 1 and t[2] == 1 and t[3] == 2 and t[4] == 3 and t[5] == nil)
+print(count(forward(nil, nil, nil)))
@@ -0,0 +1,2 @@
+local t = {}
+t[0/0] = 1
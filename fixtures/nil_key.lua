@@ -0,0 +1,2 @@
+local t, k = {}
+t[k] = 1
@@ -0,0 +1,84 @@
+local count, sum = 0, 0
+for x = 0, 1, 0.25 do
+    count = count + 1
+    sum = sum + x
+end
+assert(count == 5 and sum == 2.5)
+
+count = 0
+for x = 1, 2, 0.5 do
+    count = count + 1
+end
+assert(count == 3)
+
+count = 0
+for x = 1.0, 3 do
+    count = count + 1
+end
+assert(count == 3)
+
+-- float limits are clipped for integer loops
+count = 0
+for i = 1, 3.7 do
+    count = count + 1
+end
+assert(count == 3)
+
+count = 0
+for i = 3, 0.5, -1 do
+    count = count + 1
+end
+assert(count == 3)
+
+-- no overflow near the integer limits
+local max = 9223372036854775807
+local min = -max - 1
+count = 0
+for i = max - 2, max do
+    count = count + 1
+end
+assert(count == 3)
+
+count = 0
+for i = max - 2, 1e100 do
+    count = count + 1
+    if count > 10 then
+        break
+    end
+end
+assert(count == 3)
+
+count = 0
+for i = min + 2, min, -1 do
+    count = count + 1
+end
+assert(count == 3)
+
+count = 0
+for i = min, max, max do
+    count = count + 1
+end
+assert(count == 3)
+
+-- loops that must not run
+for i = 1, 0 do
+    assert(false)
+end
+for i = 1.5, 1 do
+    assert(false)
+end
+for i = 1, -1e100 do
+    assert(false)
+end
+for i = 0, 1e100, -1 do
+    assert(false)
+end
+
+-- modifying the loop variable does not affect the iteration
+count = 0
+for i = 1, 3 do
+    i = i * 10
+    count = count + 1
+end
+assert(count == 3)
+print("done")
@@ -0,0 +1,12 @@
+local function f() end
+local function g() end
+local t, inner = {}, {}
+t[print] = "print"
+t[f] = "f"
+t[g] = "g"
+t[inner] = "inner"
+inner.x = 1
+inner[inner] = true
+
+local f2 = f
+print(t[print], t[f2], t[g], t[inner], t[{}], f == f2, f == g, print == print, inner[inner])
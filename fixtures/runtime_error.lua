@@ -0,0 +1,18 @@
+local count = 0
+function inc()
+    count = count + 1
+    return count
+end
+
+local function fail(depth)
+    local captured = depth
+    local function get() return captured end
+    if depth == 0 then
+        assert(false, setmetatable({}, {__tostring = function() return "custom error" end}))
+    end
+    return fail(depth - 1) + get() + inc()
+end
+
+print(inc())
+fail(3)
+print("unreachable")
@@ -0,0 +1,43 @@
+local function counter()
+    local count = 0
+    return function()
+        count = count + 1
+        return count
+    end
+end
+
+local c1 = counter()
+local c2 = counter()
+local a, b, c, d = c1(), c1(), c2(), c1()
+print(a)
+print(b)
+print(c)
+print(d)
+
+local total = 0
+local function accumulate(n)
+    total = total + n
+end
+accumulate(5)
+accumulate(10)
+assert(total == 15)
+
+local function memoize(f)
+    local cache = {}
+    local hits = 0
+    return function(n)
+        if cache[n] == nil then
+            cache[n] = f(n)
+        else
+            hits = hits + 1
+        end
+        return cache[n], hits
+    end
+end
+
+local square = memoize(function(n) return n * n end)
+square(4)
+square(4)
+local value, hits = square(4)
+assert(value == 16)
+print(hits)
@@ -0,0 +1,35 @@
+local function loop(n, acc)
+    if n == 0 then
+        return acc
+    end
+    return loop(n - 1, acc + 1)
+end
+
+local function is_even(n)
+    if n == 0 then
+        return true
+    end
+    local function is_odd(m)
+        return is_even(m - 1)
+    end
+    return is_odd(n - 1)
+end
+
+local function describe(value)
+    return type(value)
+end
+
+local function shout(...)
+    return print(...)
+end
+
+local function count(...)
+    return select('#', ...)
+end
+
+assert(describe(1) == "number")
+assert(describe(nil) == "nil")
+assert(count(1, nil, 3) == 3)
+assert(shout("tail", "called") == nil)
+assert(is_even(10000))
+print(loop(1000000, 0))
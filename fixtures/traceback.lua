@@ -0,0 +1,40 @@
+local t = {}
+function t.field()
+  error("boom")
+end
+
+local obj = {}
+function obj:method()
+  t.field()
+end
+
+local proxy = setmetatable({}, {__index = function(_, key)
+  local value = obj:method()
+  return value
+end})
+
+local function up()
+  local value = proxy.key
+  return value
+end
+
+function glob()
+  local function inner()
+    up()
+  end
+  -- errors caught on the way don't end up in the traceback
+  print(pcall(error, "caught"))
+  inner()
+  return 1
+end
+
+local function trampoline()
+  glob()
+  return 1
+end
+
+local function tail()
+  return trampoline()
+end
+
+tail()
@@ -0,0 +1,45 @@
+-- `Point` (the metatable), `new_point`, `light` and the user value accessors come from the host
+Point.__index = Point
+Point.__gc = function(p)
+  local x, y = p:coords()
+  if x == 0 then print("collected", x, y) end
+end
+function Point:sum()
+  local x, y = self:coords()
+  return x + y
+end
+
+local p = new_point(1, 2)
+local q = new_point(3, 4)
+print(type(p), getmetatable(p) == Point, p == p, p == new_point(1, 2))
+print(p:coords())
+p:move(10, 10)
+print(p:coords(), p:sum())
+local r = p + q
+print(r:coords())
+print(p == q, new_point(11, 12) == p, r ~= new_point(14, 16))
+local seen = {[p] = "p"}
+print(seen[p], seen[new_point(11, 12)])
+print(pcall(Point.coords, {}))
+print(pcall(Point.coords, light(1)))
+print(pcall(function() return p.missing.x end))
+print(pcall(function() return #p end))
+print(pcall(setmetatable, p, {}))
+
+local a, b = light(1), light(1)
+print(type(a), a == b, a == light(2), getmetatable(a), ({[a] = "light"})[b])
+print(pcall(function() return a.x end))
+
+set_uservalue(p, 1, {name = "kept"})
+collectgarbage()
+print(get_uservalue(p, 1).name, get_uservalue(p, 2))
+do
+  local u = new_point(0, 0)
+  set_uservalue(u, 1, {owner = u})
+end
+collectgarbage()
+local cache = setmetatable({}, {__mode = "v"})
+cache[1] = new_point(5, 5)
+collectgarbage()
+print(cache[1])
+print(tostring(p))
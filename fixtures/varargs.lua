@@ -0,0 +1,48 @@
+local function count(...)
+    local n = select('#', ...)
+    return n
+end
+assert(count() == 0)
+assert(count(nil, nil) == 2)
+assert(count(1, 2, 3) == 3)
+
+local function first_two(...)
+    local a, b = ...
+    return a, b
+end
+local a, b = first_two(1)
+assert(a == 1 and b == nil)
+
+local function pack(...)
+    return {...}
+end
+local t = pack(1, 2, 3)
+assert(t[1] == 1 and t[3] == 3 and t[4] == nil)
+
+local function sum(...)
+    local total = 0
+    for _, v in ipairs({...}) do
+        total = total + v
+    end
+    return total
+end
+print(sum(1, 2, 3, 4))
+
+local function fixed_and_rest(x, ...)
+    local y, z = ...
+    return x, y, z
+end
+local x, y, z = fixed_and_rest("x", "y", "z")
+assert(x == "x" and y == "y" and z == "z")
+
+local function show(...)
+    print(...)
+end
+show("p", "q")
+print(select(2, "a", "b", "c"))
+print(select(-1, "a", "b", "c"))
+print(select(2.0, "a", "b", "c"))
+print(select("-2", "a", "b", "c"))
+assert(select(5, "a") == nil)
+print(pcall(select, -9223372036854775807 - 1, "a"))
+print(pcall(select, 1.5, "a"))
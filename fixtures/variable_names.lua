@@ -0,0 +1,33 @@
+local up
+local t = {inner = {}}
+local function check(f, ...)
+  print(select(2, pcall(f, ...)))
+end
+
+check(function() local cfg; return cfg.debug end)
+check(function() return missing.field end)
+check(function() return t.inner.deeper.field end)
+check(function() return up[1] end)
+check(function() up.x = 1 end)
+check(function() local cfg; cfg.x = 1 end)
+check(function() undefined() end)
+check(function() t.inner.method() end)
+check(function() t:method() end)
+check(function() up() end)
+check(function(a) return a + 1 end, {})
+check(function() return 1 - t end)
+check(function() return -up end)
+check(function() return t.inner.count * 2 end)
+check(function() local s = "x"; return s & 1 end)
+check(function() local f = 1.5; return f | 1 end)
+check(function() return 2 ~ 0.5 end)
+check(function() return "a" .. up end)
+check(function() local x; return x .. "b" end)
+check(function() return #up end)
+check(function() return t < t end)
+check(function() return 1 < "2" end)
+check(function() for i = 1, up do end end)
+check(function() return 1 % 0 end)
+check(function() local k; t[k] = 1 end)
+check(function() return setmetatable({}, {__index = 1}).x end)
+check(function() local p = setmetatable({}, {__name = "Point"}); return p + 1 end)
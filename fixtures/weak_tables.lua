@@ -0,0 +1,49 @@
+local function count(t)
+    local n = 0
+    for _ in pairs(t) do n = n + 1 end
+    return n
+end
+
+local strong = {}
+local weak_values = setmetatable({}, {__mode = "v"})
+local weak_keys = setmetatable({}, {__mode = "k"})
+weak_values[1] = {}
+weak_values[2] = strong
+weak_values.name = "strings are values"
+weak_keys[{}] = 1
+weak_keys[strong] = 2
+collectgarbage()
+print(count(weak_values), weak_values[2] == strong, count(weak_keys), weak_keys[strong])
+
+-- ephemerons: a value referring to its own key doesn't keep the entry alive
+local ephemerons = setmetatable({}, {__mode = "k"})
+do
+    local key = {}
+    ephemerons[key] = {key = key}
+    ephemerons[strong] = {}
+end
+collectgarbage()
+print(count(ephemerons), ephemerons[strong] ~= nil)
+
+-- finalizers see their object, which can be resurrected
+local log = {}
+local saved
+local mt = {__gc = function(o) log[#log + 1] = o.name; saved = o end}
+setmetatable({name = "first"}, mt)
+setmetatable({name = "second"}, mt)
+local cache = setmetatable({}, {__mode = "v"})
+cache[1] = setmetatable({name = "cached"}, {__gc = function(o) log[#log + 1] = o.name end})
+collectgarbage()
+print(#log, log[1], log[2], log[3], saved.name, cache[1])
+saved = nil
+collectgarbage()
+print(#log)
+
+-- only a __gc present when setting the metatable counts
+local late = {}
+setmetatable({}, late)
+late.__gc = function() log[#log + 1] = "late" end
+collectgarbage()
+print(#log)
+
+setmetatable({}, {__gc = function() print("closing") end})
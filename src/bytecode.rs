@@ -2,6 +2,10 @@ use header::Header;
 use function_block::FunctionBlock;
 use parser::*;
 use std::io::Write;
+#[cfg(test)]
+use instruction::Instruction;
+#[cfg(test)]
+use types::Type;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Bytecode {
@@ -20,6 +24,28 @@ impl Bytecode {
     }
 }
 
+/// A main chunk made of hand-written instructions and constants,
+/// for tests of code luac only emits for huge functions.
+#[cfg(test)]
+pub fn bytecode_from(instructions: Vec<Instruction>, constants: Vec<Type>) -> Bytecode {
+    Bytecode {
+        header: Header::default(),
+        upvalues: 1,
+        func: FunctionBlock {
+            source_name: None,
+            lines: (0, 0),
+            amount_parameters: 0,
+            is_vararg: true,
+            stack_size: 2,
+            instructions: instructions,
+            constants: constants,
+            protos: vec![],
+            upvalues: vec![],
+            debug: None,
+        },
+    }
+}
+
 impl Parsable for Bytecode {
     fn parse<R: Read + Sized>(r: &mut R) -> Self {
         Bytecode {
@@ -62,7 +88,7 @@ mod tests {
         assert_eq!(result.constants, vec![
             Type::Number(Number::Integer(42)),
             Type::Number(Number::Float(-0.08333333333)),
-            Type::String("TSHRSTR".to_owned())
+            Type::from("TSHRSTR")
         ]);
     }
 
@@ -106,6 +132,7 @@ mod tests {
         assert_eq!(result.source_name.unwrap(), "@block.lua".to_owned());
         assert_eq!(result.lines, (0, 0));
         assert_eq!(result.amount_parameters, 0);
+        assert!(result.is_vararg);
         assert_eq!(result.stack_size, 2);
         assert_eq!(result.constants, vec![]);
         assert_eq!(result.protos, vec![]);
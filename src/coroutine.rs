@@ -0,0 +1,152 @@
+use std::cmp;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use parking_lot::{Mutex, MutexGuard};
+
+use interpreter::ThreadState;
+use stack::StackLevel;
+use types::{Type, Shared, Representable};
+use gc::Object;
+
+/// Where a coroutine is in its life cycle, as reported by `coroutine.status`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// Not started yet or yielded, waiting to be resumed.
+    Suspended,
+    Running,
+    /// Resumed another coroutine and waits for it to yield or return.
+    Normal,
+    /// Returned from its body or stopped by an error.
+    Dead,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Status::Suspended => "suspended",
+            Status::Running => "running",
+            Status::Normal => "normal",
+            Status::Dead => "dead",
+        }
+    }
+}
+
+pub struct Coroutine {
+    pub status: Status,
+    /// The function to run on the first resume.
+    pub body: Option<Type>,
+    /// The stack and frames of the thread. The running thread's state lives in the `Context` instead,
+    /// dead threads don't have one.
+    pub state: Option<ThreadState>,
+}
+
+impl Coroutine {
+    /// Values this coroutine holds on to, for the garbage collector.
+    pub fn references(&self) -> Vec<Object> {
+        let mut references: Vec<Object> = self.body.iter().filter_map(Object::from_value).collect();
+        if let Some(ref state) = self.state {
+            references.extend(state.references());
+        }
+        references
+    }
+}
+
+/// A Lua thread, i.e. a coroutine with a stack and frames of its own.
+#[derive(Clone)]
+pub struct LuaThread (Shared<Coroutine>);
+
+impl LuaThread {
+    /// A suspended coroutine that calls `body` once it is resumed.
+    pub fn new(body: Type) -> Self {
+        Self::from_coroutine(Coroutine {
+            status: Status::Suspended,
+            body: Some(body),
+            state: Some(ThreadState::new()),
+        })
+    }
+
+    /// The thread running the main chunk, whose state the `Context` starts out with.
+    pub fn main() -> Self {
+        Self::from_coroutine(Coroutine {
+            status: Status::Running,
+            body: None,
+            state: None,
+        })
+    }
+
+    fn from_coroutine(coroutine: Coroutine) -> Self {
+        LuaThread (Arc::new(Mutex::new(coroutine)))
+    }
+
+    pub fn from_shared(shared: Shared<Coroutine>) -> Self {
+        LuaThread (shared)
+    }
+
+    pub fn as_object(&self) -> Object {
+        Object::Thread(self.0.clone())
+    }
+
+    /// Address identifying the thread, as shown by `tostring`.
+    pub fn as_ptr(&self) -> *const () {
+        &*self.0 as *const Mutex<Coroutine> as *const ()
+    }
+
+    pub fn lock<'a>(&'a self) -> MutexGuard<'a, Coroutine> {
+        self.0.lock()
+    }
+
+    pub fn status(&self) -> Status {
+        self.lock().status
+    }
+
+    /// Reads a slot of the stack while the thread isn't running, for its open upvalues.
+    pub fn stack_get(&self, position: StackLevel) -> Option<Type> {
+        self.lock().state.as_ref().and_then(|state| state.stack.get(position))
+    }
+
+    /// Writes a slot of the stack while the thread isn't running, for its open upvalues.
+    pub fn stack_set(&self, position: StackLevel, value: Type) {
+        if let Some(ref mut state) = self.lock().state {
+            state.stack.set(position, value)
+        }
+    }
+}
+
+// Threads are compared and hashed by identity, like tables.
+impl Eq for LuaThread {}
+impl PartialEq for LuaThread {
+    fn eq(&self, other: &LuaThread) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Ord for LuaThread {
+    fn cmp(&self, other: &LuaThread) -> cmp::Ordering {
+        self.as_ptr().cmp(&other.as_ptr())
+    }
+}
+
+impl PartialOrd for LuaThread {
+    fn partial_cmp(&self, other: &LuaThread) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for LuaThread {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ptr().hash(state)
+    }
+}
+
+impl fmt::Debug for LuaThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "thread: {:p}", self.as_ptr())
+    }
+}
+
+impl Representable for LuaThread {
+    fn repr(&self) -> String {
+        format!("thread: {:p}", self.as_ptr())
+    }
+}
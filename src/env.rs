@@ -1,36 +1,179 @@
-use types::Type;
-use table::LuaTableRaw;
-use function::{Function, NativeFunction};
+use types::{Type, Number};
+use table::{LuaTable, LuaTableRaw};
+use function::{Function, FunctionInterface, NativeFunction, NativeClosure};
 use std::sync::mpsc;
-use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use error::{LuaError, LuaResult};
+use coroutine::LuaThread;
+use string::LuaString;
+use interpreter::protected_returns;
+
+fn check_table(i: &FunctionInterface, index: usize, name: &str) -> LuaResult<LuaTable> {
+    match i.argument(index) {
+        Type::Table(table) => Ok(table),
+        other => Err(format!("bad argument #{} to '{}' (table expected, got {})", index + 1, name, other.as_type_str()).into())
+    }
+}
+
+fn check_function(i: &FunctionInterface, index: usize, name: &str) -> LuaResult<Type> {
+    match i.argument(index) {
+        func @ Type::Function(_) => Ok(func),
+        other => Err(format!("bad argument #{} to '{}' (function expected, got {})", index + 1, name, other.as_type_str()).into())
+    }
+}
+
+fn check_thread(i: &FunctionInterface, index: usize, name: &str) -> LuaResult<LuaThread> {
+    match i.argument(index) {
+        Type::Thread(thread) => Ok(thread),
+        other => Err(format!("bad argument #{} to '{}' (thread expected, got {})", index + 1, name, other.as_type_str()).into())
+    }
+}
+
+// Like `luaL_optinteger`, the argument at `index` converted to an integer, `default` if it is nil.
+fn opt_integer(i: &FunctionInterface, index: usize, name: &str, default: i64) -> LuaResult<i64> {
+    match i.argument(index) {
+        Type::Nil => Ok(default),
+        other => match other.to_number().map(|n| n.to_integer()) {
+            Some(Some(n)) => Ok(n),
+            Some(None) => Err(format!("bad argument #{} to '{}' (number has no integer representation)", index + 1, name).into()),
+            None => Err(format!("bad argument #{} to '{}' (number expected, got {})", index + 1, name, other.as_type_str()).into()),
+        }
+    }
+}
+
+fn lua_next(i: &mut FunctionInterface) -> LuaResult<()> {
+    let table = check_table(i, 0, "next")?;
+    let entry = table.next(&i.argument(1))?;
+    match entry {
+        Some((key, value)) => i.returns(vec![key, value]),
+        None => i.returns(vec![Type::Nil]),
+    }
+    Ok(())
+}
+
+fn ipairs_aux(i: &mut FunctionInterface) -> LuaResult<()> {
+    let table = check_table(i, 0, "ipairs")?;
+    let index = match i.argument(1) {
+        Type::Number(Number::Integer(n)) => n + 1,
+        other => return Err(format!("bad argument #2 to 'ipairs' (number expected, got {})", other.as_type_str()).into())
+    };
+    let value = table.lock()
+        .get(&Type::Number(Number::Integer(index)))
+        .cloned()
+        .unwrap_or(Type::Nil);
+    match value {
+        Type::Nil => i.returns(vec![Type::Nil]),
+        value => i.returns(vec![Type::Number(Number::Integer(index)), value]),
+    }
+    Ok(())
+}
+
+// The arguments converted by `tostring` and separated by tabs.
+fn print_line(i: &mut FunctionInterface) -> LuaResult<LuaString> {
+    let args = i.arguments();
+    let mut line = LuaString::default();
+    for (n, arg) in args.iter().enumerate() {
+        if n > 0 {
+            line.push(&"\t".into());
+        }
+        line.push(&i.context().tostring(&arg.as_type())?);
+    }
+    Ok(line)
+}
+
+// Continuation of `pcall` and `xpcall`, their results are those of the protected call.
+fn return_arguments(i: &mut FunctionInterface) -> LuaResult<()> {
+    let args: Vec<_> = i.arguments().iter().map(|a| a.as_type()).collect();
+    i.returns(args);
+    Ok(())
+}
 
 fn standard_functions() -> Vec<(&'static str, NativeFunction)> {
     vec![
         ("print", Box::new(
             |ref mut i| {
-                let args = i.arguments();
-                let output: Vec<_> = args.iter()
-                    .map(|t| format!("{}", t.as_type()))
-                    .collect();
-                println!("{}", output.join("\t"));
+                // strings may hold arbitrary bytes, they are written out unchanged
+                let mut line = print_line(i)?.into_bytes();
+                line.push(b'\n');
+                let stdout = io::stdout();
+                stdout.lock().write_all(&line).map_err(|e| e.to_string())?;
+                Ok(())
+            }
+        )),
+        ("tostring", Box::new(
+            |ref mut i| {
+                if i.arguments().is_empty() {
+                    return Err("bad argument #1 to 'tostring' (value expected)".into())
+                }
+                let value = i.argument(0);
+                let s = i.context().tostring(&value)?;
+                i.returns(vec![Type::String(s)]);
+                Ok(())
             }
         )),
         ("assert", Box::new(
             |ref mut i| {
-                let arg = i.get(0).as_type();
-                let will_panic = match arg {
-                    Type::Nil => true,
-                    Type::Boolean(v) => !v,
-                    _ => false 
-                };
-                if will_panic {
-                    panic!("assertion failed {:?}", arg);
+                if i.arguments().is_empty() {
+                    return Err("bad argument #1 to 'assert' (value expected)".into())
+                }
+                if !i.argument(0).truethy() {
+                    return Err(match i.argument(1) {
+                        Type::Nil => "assertion failed!".into(),
+                        message => LuaError::new(message),
+                    })
                 }
                 let args: Vec<_> = i.arguments()
                     .iter()
                     .map(|a| a.as_type())
                     .collect();
                 i.returns(args);
+                Ok(())
+            }
+        )),
+        ("error", Box::new(
+            |ref mut i| {
+                let level = match i.argument(1) {
+                    Type::Nil => 1,
+                    Type::Number(n) => match n.to_integer() {
+                        Some(level) => level,
+                        None => return Err("bad argument #2 to 'error' (number has no integer representation)".into()),
+                    },
+                    other => return Err(format!("bad argument #2 to 'error' (number expected, got {})", other.as_type_str()).into()),
+                };
+                match i.argument(0) {
+                    Type::String(ref message) if level > 0 => {
+                        let mut prefixed = LuaString::from(i.context().position(level as usize));
+                        prefixed.push(message);
+                        Err(LuaError::new(prefixed))
+                    },
+                    value => Err(LuaError::new(value)),
+                }
+            }
+        )),
+        ("pcall", Box::new(
+            |ref mut i| {
+                if i.arguments().is_empty() {
+                    return Err("bad argument #1 to 'pcall' (value expected)".into())
+                }
+                let func = i.argument(0);
+                let args: Vec<_> = i.arguments().iter().skip(1).map(|a| a.as_type()).collect();
+                let returns = i.context().protected_call_yieldable(func, args, None, Box::new(return_arguments))?;
+                i.returns(returns);
+                Ok(())
+            }
+        )),
+        ("xpcall", Box::new(
+            |ref mut i| {
+                if i.arguments().len() < 2 {
+                    return Err("bad argument #2 to 'xpcall' (value expected)".into())
+                }
+                let func = i.argument(0);
+                let handler = i.argument(1);
+                let args: Vec<_> = i.arguments().iter().skip(2).map(|a| a.as_type()).collect();
+                let returns = i.context().protected_call_yieldable(func, args, Some(handler), Box::new(return_arguments))?;
+                i.returns(returns);
+                Ok(())
             }
         )),
         ("type", Box::new(
@@ -40,20 +183,216 @@ fn standard_functions() -> Vec<(&'static str, NativeFunction)> {
                     se.as_type().as_type_str().into()
                 };
                 i.returns(vec![output]);
+                Ok(())
+            }
+        )),
+        ("select", Box::new(
+            |ref mut i| {
+                let args: Vec<_> = i.arguments()
+                    .iter()
+                    .skip(1)
+                    .map(|a| a.as_type())
+                    .collect();
+                let n = match i.argument(0) {
+                    Type::String(ref s) if s == "#" => {
+                        i.returns(vec![Type::Number(Number::Integer(args.len() as i64))]);
+                        return Ok(());
+                    },
+                    other => match other.to_number().map(|n| n.to_integer()) {
+                        Some(Some(n)) => n,
+                        Some(None) => return Err("bad argument #1 to 'select' (number has no integer representation)".into()),
+                        None => return Err(format!("bad argument #1 to 'select' (number expected, got {})", other.as_type_str()).into()),
+                    }
+                };
+                if n < 0 && n >= -(args.len() as i64) {
+                    let start = args.len() - (-n as usize);
+                    i.returns(args[start..].to_vec());
+                } else if n > 0 {
+                    i.returns(args.into_iter().skip(n as usize - 1).collect::<Vec<_>>());
+                } else {
+                    return Err("bad argument #1 to 'select' (index out of range)".into());
+                }
+                Ok(())
+            }
+        )),
+        ("setmetatable", Box::new(
+            |ref mut i| {
+                let table = check_table(i, 0, "setmetatable")?;
+                let metatable = match i.argument(1) {
+                    Type::Nil => None,
+                    Type::Table(mt) => Some(mt),
+                    _ => return Err("bad argument #2 to 'setmetatable' (nil or table expected)".into())
+                };
+                if Type::Table(table.clone()).metamethod("__metatable") != Type::Nil {
+                    return Err("cannot change a protected metatable".into())
+                }
+                table.set_metatable(metatable);
+                i.context().check_finalizer(&Type::Table(table.clone()));
+                i.returns(vec![Type::Table(table)]);
+                Ok(())
+            }
+        )),
+        ("getmetatable", Box::new(
+            |ref mut i| {
+                let value = i.argument(0);
+                let metatable = match value.metatable() {
+                    None => Type::Nil,
+                    Some(mt) => match value.metamethod("__metatable") {
+                        Type::Nil => Type::Table(mt),
+                        protected => protected,
+                    },
+                };
+                i.returns(vec![metatable]);
+                Ok(())
+            }
+        )),
+        ("collectgarbage", Box::new(
+            |ref mut i| {
+                let option = match i.argument(0) {
+                    Type::Nil => "collect".to_owned(),
+                    Type::String(s) => s.to_str_lossy().into_owned(),
+                    other => return Err(format!("bad argument #1 to 'collectgarbage' (string expected, got {})", other.as_type_str()).into())
+                };
+                let result = match option.as_str() {
+                    "collect" => {
+                        i.context().collect_garbage()?;
+                        Type::Number(Number::Integer(0))
+                    },
+                    "step" => {
+                        let kbytes = opt_integer(i, 1, "collectgarbage", 0)?.max(0) as usize;
+                        // like `lua_gc(LUA_GCSTEP)`, true only if the step completed a cycle
+                        let finished = i.context().heap().step(kbytes);
+                        if finished {
+                            i.context().collect_garbage()?;
+                        }
+                        Type::Boolean(finished)
+                    },
+                    "count" => Type::Number(Number::Float(i.context().heap().count())),
+                    "stop" => {
+                        i.context().heap().stop();
+                        Type::Number(Number::Integer(0))
+                    },
+                    "restart" => {
+                        i.context().heap().restart();
+                        Type::Number(Number::Integer(0))
+                    },
+                    "isrunning" => Type::Boolean(i.context().heap().is_running()),
+                    _ => return Err(format!("bad argument #1 to 'collectgarbage' (invalid option '{}')", option).into())
+                };
+                i.returns(vec![result]);
+                Ok(())
+            }
+        )),
+        ("next", Box::new(lua_next)),
+        ("pairs", Box::new(
+            |ref mut i| {
+                let table = check_table(i, 0, "pairs")?;
+                let next: NativeFunction = Box::new(lua_next);
+                let next: Function = next.into();
+                i.returns(vec![next.into(), table.into(), Type::Nil]);
+                Ok(())
+            }
+        )),
+        ("ipairs", Box::new(
+            |ref mut i| {
+                let table = check_table(i, 0, "ipairs")?;
+                let aux: NativeFunction = Box::new(ipairs_aux);
+                let aux: Function = aux.into();
+                i.returns(vec![aux.into(), table.into(), Type::Number(Number::Integer(0))]);
+                Ok(())
             }
         ))
     ]
 }
 
-fn testing_funcs(tx: mpsc::Sender<String>) -> Vec<(&'static str, NativeFunction)> {
+fn coroutine_functions() -> Vec<(&'static str, NativeFunction)> {
+    vec![
+        ("create", Box::new(
+            |ref mut i| {
+                let body = check_function(i, 0, "coroutine.create")?;
+                let thread = LuaThread::new(body).into();
+                i.context().track(&thread);
+                i.returns(vec![thread]);
+                Ok(())
+            }
+        )),
+        ("resume", Box::new(
+            |ref mut i| {
+                let thread = check_thread(i, 0, "coroutine.resume")?;
+                let args: Vec<_> = i.arguments().iter().skip(1).map(|a| a.as_type()).collect();
+                let returns = protected_returns(i.context().resume(&thread, args));
+                i.returns(returns);
+                Ok(())
+            }
+        )),
+        ("yield", Box::new(
+            |ref mut i| {
+                let values: Vec<_> = i.arguments().iter().map(|a| a.as_type()).collect();
+                Err(i.context().yield_values(values))
+            }
+        )),
+        ("status", Box::new(
+            |ref mut i| {
+                let thread = check_thread(i, 0, "coroutine.status")?;
+                i.returns(vec![thread.status().as_str().into()]);
+                Ok(())
+            }
+        )),
+        ("wrap", Box::new(
+            |ref mut i| {
+                let thread = LuaThread::new(check_function(i, 0, "coroutine.wrap")?).into();
+                i.context().track(&thread);
+                // the thread is an upvalue, so that the collector sees cycles through it
+                let resume: NativeFunction = Box::new(|ref mut i| {
+                    let thread = match i.upvalue(0) {
+                        Type::Thread(thread) => thread,
+                        _ => return Err("cannot resume dead coroutine".into()),
+                    };
+                    let args: Vec<_> = i.arguments().iter().map(|a| a.as_type()).collect();
+                    match i.context().resume(&thread, args) {
+                        Ok(returns) => {
+                            i.returns(returns);
+                            Ok(())
+                        },
+                        // errors are propagated, string messages get the position of the call
+                        Err(LuaError { value: Type::String(message), .. }) => {
+                            let mut prefixed = LuaString::from(i.context().position(1));
+                            prefixed.push(&message);
+                            Err(LuaError::new(prefixed))
+                        },
+                        Err(err) => Err(err),
+                    }
+                });
+                let resume: Function = NativeClosure::new(resume, vec![thread]).into();
+                let resume: Type = resume.into();
+                i.context().track(&resume);
+                i.returns(vec![resume]);
+                Ok(())
+            }
+        )),
+        ("isyieldable", Box::new(
+            |ref mut i| {
+                let yieldable = i.context().is_yieldable();
+                i.returns(vec![Type::Boolean(yieldable)]);
+                Ok(())
+            }
+        )),
+        ("running", Box::new(
+            |ref mut i| {
+                let (thread, main) = i.context().running();
+                i.returns(vec![thread.into(), Type::Boolean(main)]);
+                Ok(())
+            }
+        )),
+    ]
+}
+
+fn testing_funcs(tx: mpsc::Sender<LuaString>) -> Vec<(&'static str, NativeFunction)> {
     vec![
         ("print", Box::new(
             move |ref mut i| {
-                let args = i.arguments();
-                let output: Vec<_> = args.iter()
-                    .map(|t| format!("{}", t.as_type()))
-                    .collect();
-                tx.send(output.join("\t")).unwrap()
+                tx.send(print_line(i)?).unwrap();
+                Ok(())
             }
         )),
     ]
@@ -62,12 +401,15 @@ fn testing_funcs(tx: mpsc::Sender<String>) -> Vec<(&'static str, NativeFunction)
 pub enum Environment {
     Empty,
     LuaStandard, // lbaselib.c 453 - 483
-    Testing(mpsc::Sender<String>),
+    Testing(mpsc::Sender<LuaString>),
 }
 
 impl Environment {
     fn insert_standard(table: &mut LuaTableRaw) {
         Self::insert_funcs(table, standard_functions());
+        let mut coroutine = LuaTableRaw::new();
+        Self::insert_funcs(&mut coroutine, coroutine_functions());
+        table.insert("coroutine".into(), Type::Table(coroutine.into()));
         table.insert("_VERSION".into(), "Lua 5.3".into());
     }
 
@@ -79,7 +421,7 @@ impl Environment {
     }
 
     pub fn make(&self) -> Type {
-        let mut table: LuaTableRaw = BTreeMap::new();
+        let mut table = LuaTableRaw::new();
         match *self {
             Environment::Empty => {},
             Environment::LuaStandard => Self::insert_standard(&mut table),
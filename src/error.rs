@@ -0,0 +1,51 @@
+use std::fmt;
+use std::error::Error;
+
+use types::Type;
+
+/// A runtime error raised by a script or the VM, carrying the Lua value it was raised with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaError {
+    pub value: Type,
+    /// The call stack at the point the error was raised, for errors that no protected call caught.
+    pub traceback: Option<String>,
+}
+
+pub type LuaResult<T> = Result<T, LuaError>;
+
+impl LuaError {
+    pub fn new<T: Into<Type>>(value: T) -> Self {
+        LuaError {
+            value: value.into(),
+            traceback: None,
+        }
+    }
+}
+
+impl From<String> for LuaError {
+    fn from(message: String) -> Self {
+        LuaError::new(message)
+    }
+}
+
+impl<'a> From<&'a str> for LuaError {
+    fn from(message: &str) -> Self {
+        LuaError::new(message)
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Type::String(ref message) => write!(f, "{}", message),
+            Type::Number(ref n) => write!(f, "{}", n),
+            ref other => write!(f, "(error object is a {} value)", other.as_type_str()),
+        }
+    }
+}
+
+impl Error for LuaError {
+    fn description(&self) -> &str {
+        "lua runtime error"
+    }
+}
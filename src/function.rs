@@ -1,4 +1,6 @@
+use std::any::Any;
 use std::fmt;
+use std::mem;
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 use parking_lot::Mutex;
@@ -8,30 +10,57 @@ use types::{Type, Representable};
 use function_block::FunctionBlock;
 use interpreter::Context;
 use upvalues::SharedUpvalue;
+use error::LuaResult;
+use userdata::UserData;
 
-pub type NativeFunction = Box<Fn(&mut FunctionInterface)>;
+pub type NativeFunction = Box<Fn(&mut FunctionInterface) -> LuaResult<()>>;
 
 pub struct FunctionInterface<'a> {
-    params: &'a [StackEntry],
-    pub ret: Vec<Type>
+    params: Vec<StackEntry>,
+    upvalues: Vec<Type>,
+    pub ret: Vec<Type>,
+    context: &'a mut Context,
 }
 
 impl<'a> FunctionInterface<'a> {
-    pub fn new(params: &'a [StackEntry]) -> Self {
+    pub fn new(context: &'a mut Context, params: Vec<Type>, upvalues: Vec<Type>) -> Self {
         FunctionInterface {
-            params: params,
+            params: params.into_iter().map(StackEntry::from).collect(),
+            upvalues: upvalues,
             ret: Vec::new(),
+            context: context,
         }
     }
     pub fn arguments(&self) -> Vec<StackEntry> {
-        self.params.into()
+        self.params.clone()
     }
     pub fn get(&self, index: usize) -> &StackEntry {
         &self.params[index]
     }
+    pub fn argument(&self, index: usize) -> Type {
+        self.params.get(index)
+            .map(|entry| entry.as_type())
+            .unwrap_or(Type::Nil)
+    }
+    /// The upvalue at `index` of the running native closure, like `lua_upvalueindex`.
+    pub fn upvalue(&self, index: usize) -> Type {
+        self.upvalues.get(index).cloned().unwrap_or(Type::Nil)
+    }
+    /// The argument at `index` if it is a full userdata wrapping a `T`, like `luaL_checkudata`.
+    /// Otherwise the error names `function` and the `expected` type.
+    pub fn check_userdata<T: Any>(&self, index: usize, function: &str, expected: &str) -> LuaResult<UserData> {
+        match self.argument(index) {
+            Type::UserData(ref userdata) if userdata.is::<T>() => Ok(userdata.clone()),
+            other => Err(format!("bad argument #{} to '{}' ({} expected, got {})", index + 1, function, expected, other.type_name()).into()),
+        }
+    }
     pub fn returns<T: Into<Vec<Type>>>(&mut self, ret: T) {
         self.ret = ret.into()
     }
+    /// The calling interpreter, e.g. to call back into Lua functions.
+    pub fn context(&mut self) -> &mut Context {
+        self.context
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -65,44 +94,100 @@ impl LuaFunction {
     }
 }
 
+/// A native function with upvalues, like a C closure.
+/// Values a native function keeps should be passed as upvalues rather than captured by the Rust closure:
+/// the collector can follow upvalues, so cycles through them can be collected.
+pub struct NativeClosure {
+    pub function: NativeFunction,
+    upvalues: Mutex<Vec<Type>>,
+}
+
+impl NativeClosure {
+    pub fn new(function: NativeFunction, upvalues: Vec<Type>) -> Self {
+        NativeClosure {
+            function: function,
+            upvalues: Mutex::new(upvalues),
+        }
+    }
+
+    pub fn upvalues(&self) -> Vec<Type> {
+        self.upvalues.lock().clone()
+    }
+
+    /// Drops the upvalues, breaking any cycle through them.
+    pub fn clear(&self) {
+        let upvalues = mem::replace(&mut *self.upvalues.lock(), vec![]);
+        drop(upvalues);
+    }
+
+    pub fn call(&self, context: &mut Context, args: Vec<Type>) -> LuaResult<Vec<Type>> {
+        let mut interface = FunctionInterface::new(context, args, self.upvalues());
+        (self.function)(&mut interface).map(|_| interface.ret)
+    }
+}
+
+impl From<NativeFunction> for NativeClosure {
+    fn from(f: NativeFunction) -> NativeClosure {
+        NativeClosure::new(f, vec![])
+    }
+}
+
 #[derive(Clone)]
 pub enum Function {
-    Lua(LuaFunction),
-    Native(Arc<Mutex<NativeFunction>>),
+    Lua(Arc<LuaFunction>),
+    Native(Arc<NativeClosure>),
+}
+
+impl Function {
+    // Functions are reference types, two values are the same function if they share an allocation.
+    pub fn as_ptr(&self) -> *const () {
+        match *self {
+            Function::Lua(ref f) => &**f as *const LuaFunction as *const (),
+            Function::Native(ref f) => &**f as *const NativeClosure as *const (),
+        }
+    }
+}
+
+impl From<LuaFunction> for Function {
+    fn from(f: LuaFunction) -> Function {
+        Function::Lua(Arc::new(f))
+    }
 }
 
 impl From<NativeFunction> for Function {
     fn from(f: NativeFunction) -> Function {
-        let syncable: Arc<Mutex<NativeFunction>> = Arc::new(Mutex::new(f));
-        Function::Native(syncable)
+        Function::Native(Arc::new(f.into()))
+    }
+}
+
+impl From<NativeClosure> for Function {
+    fn from(f: NativeClosure) -> Function {
+        Function::Native(Arc::new(f))
     }
 }
 
 impl Eq for Function {}
 impl PartialEq for Function {
     fn eq(&self, other: &Self) -> bool {
-        if let (&Function::Lua(ref f_self), &Function::Lua(ref f_other)) = (self, other) {
-            return f_self == f_other
-        }
-        false
+        self.as_ptr() == other.as_ptr()
     }
 }
 
 impl Ord for Function {
-    fn cmp(&self, _: &Function) -> ::std::cmp::Ordering {
-        unimplemented!()
+    fn cmp(&self, other: &Function) -> ::std::cmp::Ordering {
+        self.as_ptr().cmp(&other.as_ptr())
     }
 }
 
 impl PartialOrd for Function {
-    fn partial_cmp(&self, _: &Function) -> Option<::std::cmp::Ordering> {
-        unimplemented!()
+    fn partial_cmp(&self, other: &Function) -> Option<::std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl Hash for Function {
-    fn hash<H: Hasher>(&self, _: &mut H) {
-        unimplemented!()
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ptr().hash(state)
     }
 }
 
@@ -117,9 +202,6 @@ impl fmt::Debug for Function {
 
 impl Representable for Function {
     fn repr(&self) -> String {
-        match *self {
-            Function::Lua(ref lf) => format!("function: {:p}", lf),
-            Function::Native(ref nf) => format!("function: {:p}", nf),
-        }
+        format!("function: {:p}", self.as_ptr())
     }
 }
\ No newline at end of file
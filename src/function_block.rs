@@ -3,14 +3,16 @@ use code::Code;
 use constants::Constants;
 use upvalues::UpvalueInfos;
 use debug::Debug;
-use instruction::InstructionContext;
-use types::Representable;
+use instruction::{Instruction, InstructionContext, DataSource, Reg};
+use instructions::*;
+use types::{Type, Representable};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionBlock {
     pub source_name: Option<String>,
     pub lines: (usize, usize),
     pub amount_parameters: u8,
+    pub is_vararg: bool,
     pub stack_size: u8,
     pub instructions: Code,
     pub constants: Constants,
@@ -29,6 +31,160 @@ impl FunctionBlock {
         }
     }
 
+    /// The chunk name shortened like the reference implementation's `luaO_chunkid`:
+    /// "@file.lua" becomes "file.lua", "=stdin" becomes "stdin" and source strings are quoted.
+    pub fn short_source(&self) -> String {
+        match self.source_name {
+            Some(ref name) if name.starts_with('@') || name.starts_with('=') => name[1..].to_owned(),
+            Some(ref name) => match name.find('\n') {
+                Some(end) => format!("[string \"{}...\"]", &name[..end]),
+                None => format!("[string \"{}\"]", name),
+            },
+            None => "?".to_owned(),
+        }
+    }
+
+    /// The source line of the instruction at `pc`, if debug information is present.
+    pub fn line(&self, pc: usize) -> Option<u32> {
+        self.debug.as_ref().and_then(|debug| debug.line_info.get(pc).cloned())
+    }
+
+    /// Name of the `n`-th (counting from 1) local variable that is active at `pc`, like `luaF_getlocalname`.
+    pub fn local_name(&self, mut n: usize, pc: usize) -> Option<&str> {
+        let debug = match self.debug {
+            Some(ref debug) => debug,
+            None => return None,
+        };
+        for local in &debug.locals {
+            if local.startpc as usize > pc {
+                break
+            }
+            if pc < local.endpc as usize {
+                n -= 1;
+                if n == 0 {
+                    return Some(&local.varname)
+                }
+            }
+        }
+        None
+    }
+
+    /// Name of the upvalue at `index`, "?" without debug information.
+    pub fn upvalue_name(&self, index: usize) -> String {
+        self.upvalues.get(index)
+            .and_then(|upvalue| upvalue.name.clone())
+            .unwrap_or_else(|| "?".to_owned())
+    }
+
+    fn constant_name(&self, index: usize) -> Option<(&'static str, String)> {
+        match self.constants.get(index) {
+            Some(&Type::String(ref name)) => Some(("constant", name.to_str_lossy().into_owned())),
+            _ => None,
+        }
+    }
+
+    // The last instruction before `lastpc` that is known to assign `reg`, like `findsetreg`.
+    // Assignments that are skipped by a forward jump might not have happened.
+    fn find_set_register(&self, lastpc: usize, reg: Reg) -> Option<usize> {
+        let mut found = None;
+        let mut jump_target = 0;
+        for (pc, instruction) in self.instructions.iter().enumerate().take(lastpc) {
+            let sets_reg = match *instruction {
+                Instruction::LOADNIL(LoadNil { start, range }) => start <= reg && reg <= start + range,
+                Instruction::TFORCALL(TForCall { a, .. }) => reg >= a + 2,
+                Instruction::CALL(Call { function, .. }) |
+                Instruction::TAILCALL(Tailcall { function, .. }) => reg >= function,
+                Instruction::JMP(Jmp { jump, .. }) => {
+                    let dest = pc as isize + 1 + jump;
+                    if (pc as isize) < dest && dest <= lastpc as isize && dest as usize > jump_target {
+                        jump_target = dest as usize;
+                    }
+                    false
+                },
+                other => other.target_register() == Some(reg),
+            };
+            if sets_reg {
+                found = if pc < jump_target { None } else { Some(pc) };
+            }
+        }
+        found
+    }
+
+    // The name of a table key for error messages, like `kname`.
+    fn key_name(&self, pc: usize, key: DataSource) -> String {
+        let name = match key {
+            DataSource::Constant(index) => self.constant_name(index),
+            DataSource::Register(reg) => self.object_name(pc, reg)
+                .and_then(|name| if name.0 == "constant" { Some(name) } else { None }),
+        };
+        name.map_or_else(|| "?".to_owned(), |(_, name)| name)
+    }
+
+    /// Describes the value in `reg` before the instruction at `pc` runs, like `getobjname`:
+    /// what kind of variable it was read from ("local", "global", "field", "upvalue",
+    /// "constant" or "method") and its name.
+    pub fn object_name(&self, pc: usize, reg: Reg) -> Option<(&'static str, String)> {
+        if let Some(name) = self.local_name(reg + 1, pc) {
+            return Some(("local", name.to_owned()))
+        }
+        let setpc = match self.find_set_register(pc, reg) {
+            Some(setpc) => setpc,
+            None => return None,
+        };
+        match self.instructions[setpc] {
+            Instruction::MOVE(Move { to, from }) if from < to => self.object_name(setpc, from),
+            Instruction::GETTABUP(GetTabUp { upvalue, constant, .. }) => {
+                let kind = if self.upvalue_name(upvalue) == "_ENV" { "global" } else { "field" };
+                Some((kind, self.key_name(setpc, constant)))
+            },
+            Instruction::GETTABLE(GetTable { b, c, .. }) => {
+                let kind = if self.local_name(b + 1, setpc) == Some("_ENV") { "global" } else { "field" };
+                Some((kind, self.key_name(setpc, c)))
+            },
+            Instruction::GETUPVAL(GetUpval { upvalue, .. }) => Some(("upvalue", self.upvalue_name(upvalue))),
+            Instruction::LOADK(LoadK { constant, .. }) => self.constant_name(constant),
+            Instruction::LOADKX(_) => match self.instructions.get(setpc + 1) {
+                Some(&Instruction::EXTRAARG(ExtraArg { value })) => self.constant_name(value),
+                _ => None,
+            },
+            Instruction::SELF(SelfOp { key, .. }) => Some(("method", self.key_name(setpc, key))),
+            _ => None,
+        }
+    }
+
+    /// Describes the function called by the instruction at `pc`, like `funcnamefromcode`.
+    /// Functions called by other instructions are metamethods, named after their event.
+    pub fn call_name(&self, pc: usize) -> Option<(&'static str, String)> {
+        let event = match self.instructions[pc] {
+            Instruction::CALL(Call { function, .. }) |
+            Instruction::TAILCALL(Tailcall { function, .. }) => return self.object_name(pc, function),
+            Instruction::TFORCALL(_) => return Some(("for iterator", "for iterator".to_owned())),
+            Instruction::SELF(_) | Instruction::GETTABUP(_) | Instruction::GETTABLE(_) => "__index",
+            Instruction::SETTABUP(_) | Instruction::SETTABLE(_) => "__newindex",
+            Instruction::ADD(_) => "__add",
+            Instruction::SUB(_) => "__sub",
+            Instruction::MUL(_) => "__mul",
+            Instruction::MOD(_) => "__mod",
+            Instruction::POW(_) => "__pow",
+            Instruction::DIV(_) => "__div",
+            Instruction::IDIV(_) => "__idiv",
+            Instruction::BAND(_) => "__band",
+            Instruction::BOR(_) => "__bor",
+            Instruction::BXOR(_) => "__bxor",
+            Instruction::SHL(_) => "__shl",
+            Instruction::SHR(_) => "__shr",
+            Instruction::UNM(_) => "__unm",
+            Instruction::BNOT(_) => "__bnot",
+            Instruction::LEN(_) => "__len",
+            Instruction::CONCAT(_) => "__concat",
+            Instruction::EQ(_) => "__eq",
+            Instruction::LT(_) => "__lt",
+            Instruction::LE(_) => "__le",
+            _ => return None,
+        };
+        Some(("metamethod", event.to_owned()))
+    }
+
     fn pretty_section<W: io::Write + Sized>(&self, w: &mut W, namebase: &str, count: usize) -> io::Result<()> {
         let s = if count != 1 { "s" } else { "" };
         writeln!(w, "\n[{} {}{}]", count, namebase, s)
@@ -88,15 +244,11 @@ impl FunctionBlock {
 
 impl Parsable for FunctionBlock {
     fn parse<R: Read + Sized>(r: &mut R) -> Self {
-        let source_name = r.parse_lua_string();
+        let source_name = r.parse_lua_string().map(|name| name.to_str_lossy().into_owned());
         // println!("source_name: {:?}", source_name);
         let lines = (u32::parse(r) as usize, u32::parse(r) as usize);
         let params = u8::parse(r);
-        let is_vararg = u8::parse(r);
-        match is_vararg {
-            0 | 2 => {},
-            _ => unimplemented!()
-        }
+        let is_vararg = u8::parse(r) != 0;
         let stack_size = u8::parse(r);
         // println!("stack_size: {:?}", stack_size);
 
@@ -124,6 +276,7 @@ impl Parsable for FunctionBlock {
             source_name: source_name,
             lines: lines,
             amount_parameters: params,
+            is_vararg: is_vararg,
             stack_size: stack_size,
             instructions: code,
             constants: constants,
@@ -140,8 +293,28 @@ mod tests {
     use std::io::Cursor;
     use types::Type;
     use header::Header;
+    use bytecode::bytecode_from;
     use upvalues::UpvalueInfo;
     use parser::{Parsable, ReadExt};
+    use instruction::Instruction;
+    use instructions::{LoadKx, ExtraArg};
+    use debug::DebugData;
+
+    #[test]
+    fn pretty_prints_loadkx_constant() {
+        let mut constants = vec![Type::Nil; 262144];
+        constants.push(Type::String("beyond Bx".into()));
+        let mut func = bytecode_from(vec![
+            Instruction::LOADKX(LoadKx { local: 0 }),
+            Instruction::EXTRAARG(ExtraArg { value: 262144 }),
+        ], constants).func;
+        func.debug = Some(DebugData::default());
+        let mut stream = Cursor::new(Vec::new());
+        func.pretty_print(&mut stream).unwrap();
+        let output = String::from_utf8(stream.into_inner()).unwrap();
+        let first = output.lines().nth(2).unwrap();
+        assert!(first.ends_with("; 262144 = \"beyond Bx\""), "unexpected line: {:?}", first);
+    }
 
     #[test]
     fn parses_assignment() {
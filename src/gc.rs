@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::mem;
+use std::fmt;
+use parking_lot::Mutex;
+
+use table::{LuaTable, LuaTableRaw};
+use function::{Function, LuaFunction, NativeClosure};
+use upvalues::Upvalue;
+use coroutine::{Coroutine, LuaThread};
+use userdata::{UserData, UserDataRaw};
+use types::Type;
+
+// Collections run once the amount of new objects reaches the amount of live objects (but at least this).
+const MIN_THRESHOLD: usize = 1024;
+
+/// A reference counted value that can be part of a reference cycle.
+///
+/// Tables, closures, upvalues, threads and userdata are registered with the `Heap`
+/// of the context that creates them. The collector never frees anything itself,
+/// it breaks the cycles of unreachable objects by clearing them and lets the reference counts do the rest.
+#[derive(Clone)]
+pub enum Object {
+    Table(Arc<Mutex<LuaTableRaw>>),
+    Closure(Arc<LuaFunction>),
+    Native(Arc<NativeClosure>),
+    Upvalue(Arc<Mutex<Upvalue>>),
+    Thread(Arc<Mutex<Coroutine>>),
+    UserData(Arc<Mutex<UserDataRaw>>),
+}
+
+#[derive(Clone)]
+enum WeakObject {
+    Table(Weak<Mutex<LuaTableRaw>>),
+    Closure(Weak<LuaFunction>),
+    Native(Weak<NativeClosure>),
+    Upvalue(Weak<Mutex<Upvalue>>),
+    Thread(Weak<Mutex<Coroutine>>),
+    UserData(Weak<Mutex<UserDataRaw>>),
+}
+
+impl WeakObject {
+    fn upgrade(&self) -> Option<Object> {
+        match *self {
+            WeakObject::Table(ref w) => w.upgrade().map(Object::Table),
+            WeakObject::Closure(ref w) => w.upgrade().map(Object::Closure),
+            WeakObject::Native(ref w) => w.upgrade().map(Object::Native),
+            WeakObject::Upvalue(ref w) => w.upgrade().map(Object::Upvalue),
+            WeakObject::Thread(ref w) => w.upgrade().map(Object::Thread),
+            WeakObject::UserData(ref w) => w.upgrade().map(Object::UserData),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match *self {
+            WeakObject::Table(ref w) => w.upgrade().is_some(),
+            WeakObject::Closure(ref w) => w.upgrade().is_some(),
+            WeakObject::Native(ref w) => w.upgrade().is_some(),
+            WeakObject::Upvalue(ref w) => w.upgrade().is_some(),
+            WeakObject::Thread(ref w) => w.upgrade().is_some(),
+            WeakObject::UserData(ref w) => w.upgrade().is_some(),
+        }
+    }
+}
+
+impl Object {
+    /// The object behind a value, `None` for values that can't form cycles.
+    pub fn from_value(value: &Type) -> Option<Object> {
+        match *value {
+            Type::Table(ref table) => Some(table.as_object()),
+            Type::Function(Function::Lua(ref func)) => Some(Object::Closure(func.clone())),
+            Type::Function(Function::Native(ref func)) => Some(Object::Native(func.clone())),
+            Type::Thread(ref thread) => Some(thread.as_object()),
+            Type::UserData(ref userdata) => Some(userdata.as_object()),
+            _ => None,
+        }
+    }
+
+    /// The value referring to this object.
+    pub fn to_value(&self) -> Type {
+        match *self {
+            Object::Table(ref t) => Type::Table(LuaTable::from_shared(t.clone())),
+            Object::Closure(ref f) => Type::Function(Function::Lua(f.clone())),
+            Object::Native(ref f) => Type::Function(Function::Native(f.clone())),
+            Object::Upvalue(_) => Type::Nil,
+            Object::Thread(ref t) => Type::Thread(LuaThread::from_shared(t.clone())),
+            Object::UserData(ref u) => Type::UserData(UserData::from_shared(u.clone())),
+        }
+    }
+
+    fn as_ptr(&self) -> *const () {
+        match *self {
+            Object::Table(ref t) => &**t as *const Mutex<LuaTableRaw> as *const (),
+            Object::Closure(ref f) => &**f as *const LuaFunction as *const (),
+            Object::Native(ref f) => &**f as *const NativeClosure as *const (),
+            Object::Upvalue(ref u) => &**u as *const Mutex<Upvalue> as *const (),
+            Object::Thread(ref t) => &**t as *const Mutex<Coroutine> as *const (),
+            Object::UserData(ref u) => &**u as *const Mutex<UserDataRaw> as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Object::Table(ref t) => Arc::strong_count(t),
+            Object::Closure(ref f) => Arc::strong_count(f),
+            Object::Native(ref f) => Arc::strong_count(f),
+            Object::Upvalue(ref u) => Arc::strong_count(u),
+            Object::Thread(ref t) => Arc::strong_count(t),
+            Object::UserData(ref u) => Arc::strong_count(u),
+        }
+    }
+
+    fn downgrade(&self) -> WeakObject {
+        match *self {
+            Object::Table(ref t) => WeakObject::Table(Arc::downgrade(t)),
+            Object::Closure(ref f) => WeakObject::Closure(Arc::downgrade(f)),
+            Object::Native(ref f) => WeakObject::Native(Arc::downgrade(f)),
+            Object::Upvalue(ref u) => WeakObject::Upvalue(Arc::downgrade(u)),
+            Object::Thread(ref t) => WeakObject::Thread(Arc::downgrade(t)),
+            Object::UserData(ref u) => WeakObject::UserData(Arc::downgrade(u)),
+        }
+    }
+
+    // Every strong reference this object holds, once per reference.
+    fn children(&self) -> Vec<Object> {
+        match *self {
+            Object::Table(ref t) => t.lock().references().iter().filter_map(Object::from_value).collect(),
+            Object::Closure(ref f) => f.upvalues.iter().map(|uv| uv.as_object()).collect(),
+            Object::Native(ref f) => f.upvalues().iter().filter_map(Object::from_value).collect(),
+            Object::Upvalue(ref u) => match *u.lock() {
+                Upvalue::Open { ref next, ref thread, .. } => vec![next.as_object(), thread.as_object()],
+                Upvalue::Closed(ref value) => Object::from_value(value).into_iter().collect(),
+            },
+            Object::Thread(ref t) => t.lock().references(),
+            Object::UserData(ref u) => u.lock().references().iter().filter_map(Object::from_value).collect(),
+        }
+    }
+
+    // Rough amount of bytes owned by this object.
+    fn size(&self) -> usize {
+        match *self {
+            Object::Table(ref t) => mem::size_of::<Mutex<LuaTableRaw>>() + t.lock().heap_size(),
+            Object::Closure(ref f) => mem::size_of::<LuaFunction>() + f.upvalues.len() * mem::size_of::<usize>(),
+            Object::Native(ref f) => mem::size_of::<NativeClosure>() + f.upvalues().len() * mem::size_of::<Type>(),
+            Object::Upvalue(_) => mem::size_of::<Mutex<Upvalue>>(),
+            Object::Thread(_) => mem::size_of::<Mutex<Coroutine>>(),
+            Object::UserData(_) => mem::size_of::<Mutex<UserDataRaw>>(),
+        }
+    }
+
+    // Drops everything the object refers to, breaking any cycle it is part of.
+    fn clear(&self) {
+        match *self {
+            Object::Table(ref t) => {
+                let contents = mem::replace(&mut *t.lock(), LuaTableRaw::new());
+                drop(contents);
+            },
+            Object::Closure(_) => {}, // closures only refer to upvalues, which are cleared instead
+            Object::Native(ref f) => f.clear(),
+            Object::Upvalue(ref u) => {
+                let value = mem::replace(&mut *u.lock(), Upvalue::Closed(Type::Nil));
+                drop(value);
+            },
+            Object::Thread(ref t) => {
+                let (body, state) = {
+                    let mut coroutine = t.lock();
+                    (coroutine.body.take(), coroutine.state.take())
+                };
+                drop((body, state));
+            },
+            Object::UserData(ref u) => u.lock().clear(),
+        }
+    }
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Object) -> bool {
+        self.as_ptr() == other.as_ptr()
+    }
+}
+
+impl fmt::Debug for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Object::Table(_) => write!(f, "Object::Table({:p})", self.as_ptr()),
+            Object::Closure(_) => write!(f, "Object::Closure({:p})", self.as_ptr()),
+            Object::Native(_) => write!(f, "Object::Native({:p})", self.as_ptr()),
+            Object::Upvalue(_) => write!(f, "Object::Upvalue({:p})", self.as_ptr()),
+            Object::Thread(_) => write!(f, "Object::Thread({:p})", self.as_ptr()),
+            Object::UserData(_) => write!(f, "Object::UserData({:p})", self.as_ptr()),
+        }
+    }
+}
+
+/// The objects a context created and the state of their collector.
+/// Every `Context` has a heap of its own, so interpreters don't share their collection settings.
+#[derive(Clone)]
+pub struct Heap {
+    // each object with its size when it was tracked or last collected
+    objects: Vec<(WeakObject, usize)>,
+    // sum of those sizes
+    bytes: usize,
+    allocated: usize,
+    threshold: usize,
+    running: bool,
+    // bytes covered by `step` since the last cycle
+    stepped: usize,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: vec![],
+            bytes: 0,
+            allocated: 0,
+            threshold: MIN_THRESHOLD,
+            running: true,
+            stepped: 0,
+        }
+    }
+
+    /// Registers a newly created object with the collector.
+    pub fn track(&mut self, object: &Object) {
+        let size = object.size();
+        self.objects.push((object.downgrade(), size));
+        self.bytes += size;
+        self.allocated += 1;
+        // keep the registry from growing without bound while collection is stopped
+        if !self.running && self.objects.len() >= 2 * self.threshold {
+            self.forget_freed();
+            self.threshold = self.objects.len().max(MIN_THRESHOLD);
+        }
+    }
+
+    // Drops the entries of objects their reference counts already freed.
+    fn forget_freed(&mut self) {
+        let mut bytes = 0;
+        self.objects.retain(|&(ref object, size)| {
+            let alive = object.is_alive();
+            if alive {
+                bytes += size;
+            }
+            alive
+        });
+        self.bytes = bytes;
+    }
+
+    /// Whether enough objects were created since the last cycle to warrant a new one.
+    pub fn should_collect(&self) -> bool {
+        self.running && self.allocated >= self.threshold
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false
+    }
+
+    pub fn restart(&mut self) {
+        self.running = true
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Like a step of `kbytes` kilobytes of an incremental collector, tells whether it completes a cycle.
+    /// There is no incremental collector: steps only add up until they cover the memory in use,
+    /// then the caller runs a full cycle. A step of size 0 always does.
+    pub fn step(&mut self, kbytes: usize) -> bool {
+        self.stepped = self.stepped.saturating_add(kbytes.saturating_mul(1024));
+        kbytes == 0 || self.stepped >= self.bytes
+    }
+
+    /// Estimated amount of memory held by tracked objects, in kilobytes like `collectgarbage("count")`.
+    /// Objects are measured when they are tracked and again by each cycle,
+    /// tables growing in between aren't accounted for until then.
+    pub fn count(&self) -> f64 {
+        self.bytes as f64 / 1024.0
+    }
+}
+
+// Reads `__mode` from the metatable: (weak keys, weak values).
+fn weak_mode(metatable: Option<LuaTable>) -> (bool, bool) {
+    let mode = metatable.and_then(|mt| mt.lock().get(&Type::from("__mode")).cloned());
+    match mode {
+        Some(Type::String(ref mode)) => (mode.as_bytes().contains(&b'k'), mode.as_bytes().contains(&b'v')),
+        _ => (false, false),
+    }
+}
+
+// What the marking phase may follow from an object.
+#[derive(Default)]
+struct Node {
+    edges: Vec<usize>,
+    // entries of weak-keyed tables, the value is only reachable through a reachable key
+    ephemerons: Vec<(usize, usize)>,
+    weak: bool,
+}
+
+struct Collector {
+    objects: Vec<Object>,
+    index: HashMap<*const (), usize>,
+    nodes: Vec<Node>,
+    marked: Vec<bool>,
+}
+
+impl Collector {
+    fn lookup(&self, value: &Type) -> Option<usize> {
+        Object::from_value(value).and_then(|o| self.index.get(&o.as_ptr()).cloned())
+    }
+
+    fn node(&self, object: &Object) -> Node {
+        let table = match *object {
+            Object::Table(ref t) => t,
+            ref other => return Node {
+                edges: other.children().iter().filter_map(|c| self.index.get(&c.as_ptr()).cloned()).collect(),
+                ..Node::default()
+            },
+        };
+        let (pairs, metatable) = {
+            let raw = table.lock();
+            (raw.pairs(), raw.metatable())
+        };
+        let mut node = Node::default();
+        if let Some(ref mt) = metatable {
+            node.edges.extend(self.lookup(&Type::Table(mt.clone())));
+        }
+        let (weak_keys, weak_values) = weak_mode(metatable);
+        node.weak = weak_keys || weak_values;
+        for (key, value) in pairs {
+            let (key, value) = (self.lookup(&key), self.lookup(&value));
+            if !weak_keys {
+                node.edges.extend(key);
+            }
+            match (weak_keys, weak_values, key, value) {
+                (_, true, _, _) | (_, _, _, None) => {},
+                (false, false, _, Some(value)) | (true, false, None, Some(value)) => node.edges.push(value),
+                (true, false, Some(key), Some(value)) => node.ephemerons.push((key, value)),
+            }
+        }
+        node
+    }
+
+    fn mark(&mut self, mut pending: Vec<usize>) {
+        loop {
+            while let Some(i) = pending.pop() {
+                if self.marked[i] {
+                    continue
+                }
+                self.marked[i] = true;
+                pending.extend(self.nodes[i].edges.iter().filter(|&&child| !self.marked[child]));
+            }
+            // values of ephemerons become reachable once their key and table are
+            for (i, node) in self.nodes.iter().enumerate() {
+                if self.marked[i] {
+                    pending.extend(node.ephemerons.iter()
+                        .filter(|&&(key, value)| self.marked[key] && !self.marked[value])
+                        .map(|&(_, value)| value));
+                }
+            }
+            if pending.is_empty() {
+                return
+            }
+        }
+    }
+
+    fn is_dead(&self, value: &Type) -> bool {
+        self.lookup(value).map_or(false, |i| !self.marked[i])
+    }
+
+    // Removes entries referring to unmarked objects from weak tables.
+    fn clear_weak(&self, keys: bool, values: bool) {
+        for (object, node) in self.objects.iter().zip(self.nodes.iter()) {
+            if let (&Object::Table(ref table), true) = (object, node.weak) {
+                let (weak_keys, weak_values) = weak_mode(table.lock().metatable());
+                let (keys, values) = (keys && weak_keys, values && weak_values);
+                table.lock().remove_where(|key, value| {
+                    (keys && self.is_dead(key)) || (values && self.is_dead(value))
+                });
+            }
+        }
+    }
+}
+
+impl Heap {
+    /// Runs a full mark & sweep cycle over the tracked objects.
+    ///
+    /// Marking doesn't only start at `roots`: like trial deletion, every object whose strong count
+    /// exceeds the references the other tracked objects hold to it is a root as well.
+    /// Not all references can be traced, the host holds on to values of its own and native functions
+    /// may capture values in Rust closures instead of their upvalues (see `NativeClosure`).
+    /// Treating them as roots keeps those objects alive, at the price of never collecting
+    /// garbage that such a reference points to.
+    ///
+    /// `finalizable` holds the objects with a `__gc` metamethod. Unreachable ones are resurrected
+    /// for this cycle, removed from the list and returned in the order their finalizers should run.
+    pub fn collect(&mut self, roots: Vec<Object>, finalizable: &mut Vec<Object>) -> Vec<Object> {
+        let objects: Vec<Object> = self.objects.iter().filter_map(|&(ref o, _)| o.upgrade()).collect();
+        self.objects.clear();
+        let finalize = sweep(objects, roots, finalizable, &mut self.objects);
+        self.bytes = self.objects.iter().map(|&(_, size)| size).sum();
+        self.allocated = 0;
+        self.stepped = 0;
+        self.threshold = self.objects.len().max(MIN_THRESHOLD);
+        finalize
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Heap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Heap {{ objects: {}, running: {} }}", self.objects.len(), self.running)
+    }
+}
+
+// Marks from the roots and clears whatever wasn't reached,
+// the survivors are added to `live` together with their current size.
+fn sweep(objects: Vec<Object>, roots: Vec<Object>, finalizable: &mut Vec<Object>, live: &mut Vec<(WeakObject, usize)>) -> Vec<Object> {
+    let index: HashMap<*const (), usize> = objects.iter()
+        .enumerate()
+        .map(|(i, o)| (o.as_ptr(), i))
+        .collect();
+    // neither our own reference in `objects` nor the finalizer list count as external
+    let mut strong: Vec<usize> = objects.iter().map(|o| o.strong_count() - 1).collect();
+    for object in finalizable.iter() {
+        if let Some(&i) = index.get(&object.as_ptr()) {
+            strong[i] -= 1;
+        }
+    }
+    let mut internal = vec![0; objects.len()];
+    for object in &objects {
+        for child in object.children() {
+            if let Some(&i) = index.get(&child.as_ptr()) {
+                internal[i] += 1;
+            }
+        }
+    }
+
+    let mut collector = Collector {
+        nodes: vec![],
+        marked: vec![false; objects.len()],
+        objects: objects,
+        index: index,
+    };
+    collector.nodes = collector.objects.iter().map(|o| collector.node(o)).collect();
+    let pending: Vec<usize> = (0..collector.objects.len())
+        .filter(|&i| strong[i] > internal[i])
+        .chain(roots.iter().filter_map(|root| collector.index.get(&root.as_ptr()).cloned()))
+        .collect();
+    drop(roots);
+    collector.mark(pending);
+
+    // weak values go away before resurrecting objects with finalizers, weak keys only afterwards
+    collector.clear_weak(false, true);
+    let mut finalize = vec![];
+    let mut i = finalizable.len();
+    while i > 0 {
+        i -= 1;
+        let dead = collector.index.get(&finalizable[i].as_ptr()).map_or(false, |&j| !collector.marked[j]);
+        if dead {
+            finalize.push(finalizable.remove(i));
+        }
+    }
+    let resurrected = finalize.iter()
+        .filter_map(|o| collector.index.get(&o.as_ptr()).cloned())
+        .collect();
+    collector.mark(resurrected);
+    collector.clear_weak(true, true);
+
+    for (object, reachable) in collector.objects.into_iter().zip(collector.marked) {
+        if reachable {
+            live.push((object.downgrade(), object.size()));
+        } else {
+            object.clear();
+        }
+    }
+    finalize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weak_table(table: &LuaTable) -> Weak<Mutex<LuaTableRaw>> {
+        match table.as_object() {
+            Object::Table(ref t) => Arc::downgrade(t),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn collects_self_referencing_tables() {
+        let mut heap = Heap::new();
+        let table = LuaTable::new();
+        heap.track(&table.as_object());
+        table.lock().insert("self".into(), Type::Table(table.clone()));
+        let weak = weak_table(&table);
+        drop(table);
+        assert!(weak.upgrade().is_some());
+        heap.collect(vec![], &mut vec![]);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn keeps_externally_referenced_cycles() {
+        let mut heap = Heap::new();
+        let a = LuaTable::new();
+        let b = LuaTable::new();
+        heap.track(&a.as_object());
+        heap.track(&b.as_object());
+        a.lock().insert("b".into(), Type::Table(b.clone()));
+        b.lock().insert("a".into(), Type::Table(a.clone()));
+        drop(b);
+        heap.collect(vec![], &mut vec![]);
+        let b = a.lock().get(&"b".into()).cloned().unwrap();
+        let b = as_type_variant!(b, Type::Table);
+        assert_eq!(b.lock().get(&"a".into()), Some(&Type::Table(a.clone())));
+    }
+
+    #[test]
+    fn heaps_are_independent() {
+        let (mut first, mut second) = (Heap::new(), Heap::new());
+        first.stop();
+        assert!(!first.is_running() && second.is_running());
+
+        let cycle = LuaTable::new();
+        cycle.lock().insert("self".into(), Type::Table(cycle.clone()));
+        first.track(&cycle.as_object());
+        let weak = weak_table(&cycle);
+        drop(cycle);
+        assert!(first.count() > 0.0);
+        assert_eq!(second.count(), 0.0);
+
+        // only the heap that tracks an object collects it
+        second.collect(vec![], &mut vec![]);
+        assert!(weak.upgrade().is_some());
+        first.collect(vec![], &mut vec![]);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(first.count(), 0.0);
+    }
+
+    #[test]
+    fn steps_add_up_to_a_cycle() {
+        let mut heap = Heap::new();
+        let tables: Vec<LuaTable> = (0..100).map(|_| LuaTable::new()).collect();
+        for table in &tables {
+            heap.track(&table.as_object());
+        }
+        let kbytes = heap.count().ceil() as usize;
+        assert!(kbytes > 1);
+        assert!(!heap.step(1));
+        assert!(heap.step(kbytes - 1));
+        heap.collect(vec![], &mut vec![]);
+        assert!(!heap.step(1));
+        assert!(heap.step(0));
+    }
+}
@@ -7,6 +7,7 @@ use byteorder;
 pub use types::{Type, Representable};
 pub use interpreter::Context;
 pub use stack::{StackEntry, Stack};
+pub use error::{LuaError, LuaResult};
 
 use instructions::*;
 
@@ -27,7 +28,7 @@ pub trait LoadInstruction: Sized {
 }
 
 pub trait InstructionOps: fmt::Debug {
-    fn exec(&self, _: &mut Context);
+    fn exec(&self, _: &mut Context) -> LuaResult<()>;
     fn debug_info(&self, InstructionContext) -> Vec<String> { vec![] }
 }
 
@@ -36,11 +37,13 @@ pub trait InstructionOps: fmt::Debug {
 pub enum Instruction {
     MOVE(Move),
     LOADK(LoadK),
+    LOADKX(LoadKx),
     LOADBOOL(LoadBool),
     LOADNIL(LoadNil),
     GETUPVAL(GetUpval),
     GETTABUP(GetTabUp),
     SETTABUP(SetTabUp),
+    SETUPVAL(SetUpval),
     GETTABLE(GetTable),
     SETTABLE(SetTable),
     NEWTABLE(NewTable),
@@ -73,7 +76,12 @@ pub enum Instruction {
     RETURN(Return),
     FORLOOP(ForLoop),
     FORPREP(ForPrep),
+    TFORCALL(TForCall),
+    TFORLOOP(TForLoop),
+    SETLIST(SetList),
     CLOSURE(Closure),
+    VARARG(Vararg),
+    EXTRAARG(ExtraArg),
 }
 
 macro_rules! match_trait_as_impl {
@@ -90,11 +98,13 @@ impl Instruction {
         match_trait_as_impl!(self, [
             Instruction::MOVE,
             Instruction::LOADK,
+            Instruction::LOADKX,
             Instruction::LOADBOOL,
             Instruction::LOADNIL,
             Instruction::GETUPVAL,
             Instruction::GETTABUP,
             Instruction::SETTABUP,
+            Instruction::SETUPVAL,
             Instruction::GETTABLE,
             Instruction::SETTABLE,
             Instruction::NEWTABLE,
@@ -127,11 +137,58 @@ impl Instruction {
             Instruction::RETURN,
             Instruction::FORLOOP,
             Instruction::FORPREP,
-            Instruction::CLOSURE
+            Instruction::TFORCALL,
+            Instruction::TFORLOOP,
+            Instruction::SETLIST,
+            Instruction::CLOSURE,
+            Instruction::VARARG,
+            Instruction::EXTRAARG
         ] => as &InstructionOps)
     }
-    pub fn exec(&self, i: &mut Context) {
-        self.as_ops().exec(i);
+    pub fn exec(&self, i: &mut Context) -> LuaResult<()> {
+        self.as_ops().exec(i)
+    }
+
+    /// The register R(A) if the instruction assigns to it, like `testAMode` in the reference implementation.
+    pub fn target_register(&self) -> Option<Reg> {
+        match *self {
+            Instruction::MOVE(Move { to, .. }) => Some(to),
+            Instruction::LOADK(LoadK { local, .. }) |
+            Instruction::LOADKX(LoadKx { local }) => Some(local),
+            Instruction::LOADBOOL(LoadBool { reg, .. }) |
+            Instruction::GETUPVAL(GetUpval { reg, .. }) |
+            Instruction::GETTABUP(GetTabUp { reg, .. }) |
+            Instruction::TESTSET(TestSet { reg, .. }) => Some(reg),
+            Instruction::LOADNIL(LoadNil { start, .. }) => Some(start),
+            Instruction::GETTABLE(GetTable { a, .. }) |
+            Instruction::NEWTABLE(NewTable { a, .. }) |
+            Instruction::SELF(SelfOp { a, .. }) |
+            Instruction::ADD(Add { a, .. }) |
+            Instruction::SUB(Sub { a, .. }) |
+            Instruction::MUL(Mul { a, .. }) |
+            Instruction::MOD(Mod { a, .. }) |
+            Instruction::POW(Pow { a, .. }) |
+            Instruction::DIV(Div { a, .. }) |
+            Instruction::IDIV(IDiv { a, .. }) |
+            Instruction::BAND(BAnd { a, .. }) |
+            Instruction::BOR(BOr { a, .. }) |
+            Instruction::BXOR(BXor { a, .. }) |
+            Instruction::SHL(Shl { a, .. }) |
+            Instruction::SHR(Shr { a, .. }) |
+            Instruction::UNM(Unm { a, .. }) |
+            Instruction::BNOT(BNot { a, .. }) |
+            Instruction::NOT(Not { a, .. }) |
+            Instruction::LEN(Len { a, .. }) |
+            Instruction::CONCAT(Concat { a, .. }) |
+            Instruction::FORLOOP(ForLoop { a, .. }) |
+            Instruction::FORPREP(ForPrep { a, .. }) |
+            Instruction::TFORLOOP(TForLoop { a, .. }) |
+            Instruction::CLOSURE(Closure { a, .. }) |
+            Instruction::VARARG(Vararg { a, .. }) => Some(a),
+            Instruction::CALL(Call { function, .. }) |
+            Instruction::TAILCALL(Tailcall { function, .. }) => Some(function),
+            _ => None,
+        }
     }
 }
 
@@ -146,14 +203,14 @@ impl Parsable for Instruction {
         match opcode {
             00 => Instruction::MOVE(Move::load(data)),
             01 => Instruction::LOADK(LoadK::load(data)),
-            // TODO: 2 LOADKX
+            02 => Instruction::LOADKX(LoadKx::load(data)),
             03 => Instruction::LOADBOOL(LoadBool::load(data)),
             04 => Instruction::LOADNIL(LoadNil::load(data)),
             05 => Instruction::GETUPVAL(GetUpval::load(data)),
             06 => Instruction::GETTABUP(GetTabUp::load(data)),
             07 => Instruction::GETTABLE(GetTable::load(data)),
             08 => Instruction::SETTABUP(SetTabUp::load(data)),
-            // TODO: 9 SETUPVAL
+            09 => Instruction::SETUPVAL(SetUpval::load(data)),
             10 => Instruction::SETTABLE(SetTable::load(data)),
             11 => Instruction::NEWTABLE(NewTable::load(data)),
             12 => Instruction::SELF(SelfOp::load(data)),
@@ -185,12 +242,12 @@ impl Parsable for Instruction {
             38 => Instruction::RETURN(Return::load(data)),
             39 => Instruction::FORLOOP(ForLoop::load(data)),
             40 => Instruction::FORPREP(ForPrep::load(data)),
-            // TODO: 41 TFORCALL
-            // TODO: 42 TFORLOOP
-            // TODO: 43 SETLIST
+            41 => Instruction::TFORCALL(TForCall::load(data)),
+            42 => Instruction::TFORLOOP(TForLoop::load(data)),
+            43 => Instruction::SETLIST(SetList::load(data)),
             44 => Instruction::CLOSURE(Closure::load(data)),
-            // TODO: 45 VARARG
-            // TODO: 46 EXTRAARG
+            45 => Instruction::VARARG(Vararg::load(data)),
+            46 => Instruction::EXTRAARG(ExtraArg::load(data)),
             invalid => panic!("invalid opcode: {:?}, all: {:?}", invalid, data)
         }
     }
@@ -224,6 +281,11 @@ pub fn parse_A_sBx(d: u32) -> (Reg, isize) {
     (a as Reg, sBx)
 }
 
+#[allow(non_snake_case)]
+pub fn parse_Ax(d: u32) -> usize {
+    get_bits!(d, 6 => 32) as usize
+}
+
 #[allow(non_snake_case)]
 pub fn parse_A_B_C(d: u32) -> (Reg, Reg, Reg) {
     let a = get_bits!(d, 6 => 14);
@@ -247,6 +309,23 @@ impl DataSource {
     }
 }
 
+/// Where an instruction read a value from, so that error messages can name the variable behind it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(Reg),
+    Upvalue(usize),
+    Constant(usize),
+}
+
+impl From<DataSource> for Operand {
+    fn from(source: DataSource) -> Self {
+        match source {
+            DataSource::Register(index) => Operand::Register(index),
+            DataSource::Constant(index) => Operand::Constant(index),
+        }
+    }
+}
+
 impl From<usize> for DataSource {
     fn from(other: usize) -> Self {
         if other >= 0b1_0000_0000 {
@@ -321,6 +400,16 @@ mod tests {
         assert_eq!(instruction, Instruction::RETURN(Return {base: 0, count: Count::Known(0)}));
     }
 
+    #[test]
+    fn parses_setlist_with_extraarg() {
+        let data = &[0x2B, 0x00, 0x80, 0x00, 0xAE, 0x96, 0x00, 0x00];
+        let mut reader = Cursor::new(data);
+        let setlist = Instruction::parse(&mut reader);
+        let extraarg = Instruction::parse(&mut reader);
+        assert_eq!(setlist, Instruction::SETLIST(SetList { a: 0, count: Count::Known(1), batch: 0 }));
+        assert_eq!(extraarg, Instruction::EXTRAARG(ExtraArg { value: 0x25A }));
+    }
+
     #[test]
     fn parses_gettabup() {
         let data = &[0b00000110, 0b00000000, 0b01000000, 0];
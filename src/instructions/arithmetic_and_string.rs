@@ -1,5 +1,51 @@
 use instruction::*;
-use types::Number;
+use types::{ArithOp, Number};
+use string::LuaString;
+
+// Like `Type::to_number`, but strings only take part in arithmetic as floats.
+fn coerce(op: ArithOp, value: &Type) -> Option<Number> {
+    match *value {
+        Type::String(_) if !op.is_bitwise() => value.to_number().map(|n| Number::Float(n.into())),
+        _ => value.to_number(),
+    }
+}
+
+// Applies `op` to two values, coercing numeric strings to numbers
+// and falling back to the metamethod of the first operand that has one.
+// `operands` tell where `b` and `c` were read from, to name the culprit in errors.
+fn arith(context: &mut Context, op: ArithOp, b: &Type, c: &Type, operands: (Operand, Operand)) -> LuaResult<Type> {
+    if let (Some(x), Some(y)) = (coerce(op, b), coerce(op, c)) {
+        match Number::arith(op, x, y) {
+            Ok(result) => return Ok(Type::Number(result)),
+            // operands without an integer representation may still have metamethods
+            Err(ref msg) if !op.is_bitwise() => return Err(context.runtime_error(msg.clone())),
+            Err(_) => {},
+        }
+    }
+    let handler = match b.metamethod(op.event()) {
+        Type::Nil => c.metamethod(op.event()),
+        handler => handler,
+    };
+    if handler != Type::Nil {
+        return Ok(context.call(handler, vec![b.clone(), c.clone()])?
+            .into_iter()
+            .next()
+            .unwrap_or(Type::Nil))
+    }
+    match (b, c) {
+        // like the reference implementation, blame the first operand that isn't an integer
+        (&Type::Number(x), &Type::Number(_)) if op.is_bitwise() => {
+            let operand = if x.to_integer().is_none() { operands.0 } else { operands.1 };
+            let info = context.variable_info(operand);
+            Err(context.runtime_error(format!("number{} has no integer representation", info)))
+        },
+        _ => {
+            let (culprit, operand) = if b.to_number().is_none() { (b, operands.0) } else { (c, operands.1) };
+            let action = if op.is_bitwise() { "perform bitwise operation on" } else { "perform arithmetic on" };
+            Err(context.type_error(culprit, action, Some(operand)))
+        },
+    }
+}
 
 macro_rules! arith {
     ($name:ident, $op:expr) => (
@@ -22,108 +68,42 @@ macro_rules! arith {
         }
 
         impl InstructionOps for $name {
-            fn exec(&self, context: &mut Context) {
+            fn exec(&self, context: &mut Context) -> LuaResult<()> {
                 let b = self.b.get_from(context);
                 let c = self.c.get_from(context);
-                if let (&Type::Number(ref b), &Type::Number(ref c)) = (&b, &c) {
-                    let result = $op(*b, *c);
-                    context.stack[self.a] = StackEntry::Type(Type::Number(result));
-                } else {
-                    panic!("attempt to perform arithmetic on ({}, {})", b.as_type_str(), c.as_type_str())
-                }
+                let result = arith(context, $op, &b, &c, (self.b.into(), self.c.into()))?;
+                context.stack[self.a] = StackEntry::Type(result);
+                Ok(())
             }
         }
     )
 }
 
-macro_rules! warp_as_number_type {
-    ($f:expr) => (|a, b| {
-        match a {
-            Number::Integer(a) => {
-                match b {
-                    Number::Integer(b) => Number::Integer($f(a, b)),
-                    Number::Float(b) => Number::Float($f(a as f64, b))
-                }
-            },
-            Number::Float(a) => {
-                match b {
-                    Number::Integer(b) => Number::Float($f(a, b as f64)),
-                    Number::Float(b) => Number::Float($f(a, b))
-                }
-            }
-        }
-    })
-}
-
-macro_rules! as_integer_repr {
-    ($f:expr) => (|a, b| {
-        if let (&Number::Integer(ref a), &Number::Integer(ref b)) = (&a, &b) {
-            Number::Integer($f(*a, *b))
-        } else {
-            panic!("number has no integer representation ({:?}, {:?})", a, b)
-        }
-    })
-}
-
-macro_rules! wrapped_type_as_integer {
-    ($f:expr) => (|val| {
-        if let Type::Number(Number::Integer(v)) = val {
-            Type::Number(Number::Integer($f(v)))
-        } else {
-            panic!("{} has no integer representation", val.as_type_str())
-        }
-    })
-}
-
 
 // ADD,         A B C   R(A) := RK(B) + RK(C)                           13
-arith!(Add, warp_as_number_type!(|a, b| a + b));
+arith!(Add, ArithOp::Add);
 // SUB,         A B C   R(A) := RK(B) - RK(C)                           14
-arith!(Sub, warp_as_number_type!(|a, b| a - b));
+arith!(Sub, ArithOp::Sub);
 // MUL,         A B C   R(A) := RK(B) * RK(C)                           15
-arith!(Mul, warp_as_number_type!(|a, b| a * b));
+arith!(Mul, ArithOp::Mul);
 // MOD,         A B C   R(A) := RK(B) % RK(C)                           16
-arith!(Mod, warp_as_number_type!(|a, b| a % b));
+arith!(Mod, ArithOp::Mod);
 // POW,         A B C   R(A) := RK(B) ^ RK(C)                           17
-arith!(Pow, |a, b| {
-    match a {
-        Number::Integer(a) => {
-            match b {
-                Number::Integer(b) => Number::Integer(a.pow(b as u32)),
-                Number::Float(b) => Number::Float((a as f64).powf(b)),
-            }
-        },
-        Number::Float(a) => {
-            match b {
-                Number::Integer(b) => Number::Float(a.powi(b as i32)),
-                Number::Float(b) => Number::Float(a.powf(b))
-            }
-        }
-    }
-});
+arith!(Pow, ArithOp::Pow);
 // DIV,         A B C   R(A) := RK(B) / RK(C)                           18
-arith!(Div, warp_as_number_type!(|a, b| a / b));
+arith!(Div, ArithOp::Div);
 // IDIV,        A B C   R(A) := RK(B) // RK(C)                          19
-arith!(IDiv, |a: Number, b: Number| {
-    let a: f64 = a.into();
-    let b: f64 = b.into();
-    Number::Integer((a / b) as i64)
-});
-
-// FIXME: lua integers are two's complement
-// =>  lua: -1 >> 1 == 9223372036854775807
-// => rust: -1 >> 1 == -1
-
+arith!(IDiv, ArithOp::IDiv);
 // BAND,        A B C   R(A) := RK(B) & RK(C)                           20
-arith!(BAnd, as_integer_repr!(|a, b| a & b));
+arith!(BAnd, ArithOp::BAnd);
 // BOR,         A B C   R(A) := RK(B) | RK(C)                           21
-arith!(BOr, as_integer_repr!(|a, b| a | b));
+arith!(BOr, ArithOp::BOr);
 // BXOR,        A B C   R(A) := RK(B) ~ RK(C)                           22
-arith!(BXor, as_integer_repr!(|a, b| a ^ b));
+arith!(BXor, ArithOp::BXor);
 // SHL,         A B C   R(A) := RK(B) << RK(C)                          23
-arith!(Shl, as_integer_repr!(|a, b| a << b));
+arith!(Shl, ArithOp::Shl);
 // SHR,         A B C   R(A) := RK(B) >> RK(C)                          24
-arith!(Shr, as_integer_repr!(|a, b| a >> b));
+arith!(Shr, ArithOp::Shr);
 
 
 macro_rules! unary {
@@ -145,30 +125,41 @@ macro_rules! unary {
         }
 
         impl InstructionOps for $name {
-            fn exec(&self, context: &mut Context) {
+            fn exec(&self, context: &mut Context) -> LuaResult<()> {
                 let value = context.stack[self.b].as_type();
-                let result = $op(value);
+                let result = $op(&mut *context, value, Operand::Register(self.b))?;
                 context.stack[self.a] = StackEntry::Type(result);
+                Ok(())
             }
         }
     )
 }
 
 // UNM,         A B     R(A) := -R(B)                                   25
-unary!(Unm, wrapped_type_as_integer!(|value: i64| -value));
+unary!(Unm, |context, value: Type, operand| arith(context, ArithOp::Unm, &value, &value, (operand, operand)));
 // BNOT,        A B     R(A) := ~R(B)                                   26
-unary!(BNot, wrapped_type_as_integer!(|value: i64| !value));
+unary!(BNot, |context, value: Type, operand| arith(context, ArithOp::BNot, &value, &value, (operand, operand)));
 // NOT,         A B     R(A) := not R(B)                                27
-unary!(Not, |value: Type| Type::Boolean(match value {
-    Type::Boolean(v) => !v,
-    _ => false
-}));
+unary!(Not, |_, value: Type, _| -> LuaResult<Type> {
+    Ok(Type::Boolean(match value {
+        Type::Boolean(v) => !v,
+        _ => false
+    }))
+});
 // LEN,         A B     R(A) := length of R(B)                          28
-unary!(Len, |value: Type| Type::Number(Number::Integer(match value {
-    Type::String(s) => s.len() as i64,
-    Type::Table(_) => unimplemented!(),
-    _ => panic!("attempt to get length of a {} value ({:?})", value.as_type_str(), value)
-})));
+unary!(Len, |context: &mut Context, value: Type, operand| -> LuaResult<Type> {
+    if let Type::String(ref s) = value {
+        return Ok(Type::Number(Number::Integer(s.len() as i64)))
+    }
+    match (value.metamethod("__len"), &value) {
+        (Type::Nil, &Type::Table(ref t)) => Ok(Type::Number(Number::Integer(t.border()))),
+        (Type::Nil, _) => Err(context.type_error(&value, "get length of", Some(operand))),
+        (handler, _) => Ok(context.call(handler, vec![value.clone()])?
+            .into_iter()
+            .next()
+            .unwrap_or(Type::Nil)),
+    }
+});
 
 // 29: CONCAT   A B C   R(A) := R(B).. ... ..R(C)
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -185,20 +176,52 @@ impl LoadInstruction for Concat {
     }
 }
 
+// Strings and numbers are concatenated directly, anything else goes through `__concat`.
+fn concat_piece(value: &Type) -> Option<LuaString> {
+    match *value {
+        Type::String(ref s) => Some(s.clone()),
+        Type::Number(ref n) => Some(n.repr().into()),
+        _ => None,
+    }
+}
+
 impl InstructionOps for Concat {
-    fn exec(&self, context: &mut Context) {
-        let result = {
-            let vals = &context.stack[self.b .. self.c + 1];
-            vals.iter()
-                .map(|v| v.as_type())
-                .map(|v| match v {
-                    Type::String(ref s) => s.clone(),
-                    Type::Number(ref n) => n.repr(),
-                    _ => panic!("attempted to concatenate a {} value", v.as_type_str())
-                })
-                .collect::<Vec<_>>()
-                .join("")
-        };
-        context.stack[self.a] = StackEntry::Type(Type::String(result));
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let mut values: Vec<Type> = (self.b..self.c + 1)
+            .map(|i| context.stack[i].as_type())
+            .collect();
+        // like the reference implementation, work right to left and join runs of strings at once
+        while values.len() > 1 {
+            let n = values.len();
+            let pieces: Option<Vec<LuaString>> = values[n - 2..].iter().map(concat_piece).collect();
+            if pieces.is_some() {
+                let mut start = n - 2;
+                while start > 0 && concat_piece(&values[start - 1]).is_some() {
+                    start -= 1;
+                }
+                let mut joined = LuaString::default();
+                for value in values.drain(start..) {
+                    joined.push(&concat_piece(&value).unwrap());
+                }
+                values.push(Type::String(joined));
+                continue
+            }
+            let rhs = values.pop().unwrap();
+            let lhs = values.pop().unwrap();
+            let handler = match lhs.metamethod("__concat") {
+                Type::Nil => rhs.metamethod("__concat"),
+                handler => handler,
+            };
+            if handler == Type::Nil {
+                // the pair being joined sits at the end of the remaining registers
+                let lhs_reg = self.b + values.len();
+                let (culprit, reg) = if concat_piece(&lhs).is_some() { (&rhs, lhs_reg + 1) } else { (&lhs, lhs_reg) };
+                return Err(context.type_error(culprit, "concatenate", Some(Operand::Register(reg))))
+            }
+            let result = context.call(handler, vec![lhs, rhs])?.into_iter().next().unwrap_or(Type::Nil);
+            values.push(result);
+        }
+        context.stack[self.a] = StackEntry::Type(values.pop().unwrap());
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -17,9 +17,44 @@ impl LoadInstruction for Closure {
 
 
 impl InstructionOps for Closure {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let proto = context.ci().func.protos[self.b].clone();
-        let func = LuaFunction::new(context, proto);
-        context.stack[self.a] = Type::Function(Function::Lua(func)).into();
+        let func = Type::Function(LuaFunction::new(context, proto).into());
+        context.track(&func);
+        context.stack[self.a] = func.into();
+        context.check_gc();
+        Ok(())
+    }
+}
+
+// 45: VARARG   A B     R(A), R(A+1), ..., R(A+B-2) = vararg
+// if (B == 0) then all varargs are copied and 'top' is set accordingly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vararg { pub a: Reg, pub count: Count }
+
+impl LoadInstruction for Vararg {
+    fn load(d: u32) -> Self {
+        let (a, b) = parse_A_B(d);
+        Vararg {
+            a: a,
+            count: b.into(),
+        }
+    }
+}
+
+impl InstructionOps for Vararg {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let mut varargs = context.ci().varargs.clone();
+        if let Count::Known(count) = self.count {
+            varargs.resize(count, Type::Nil);
+        }
+        let amount = varargs.len();
+        for (i, value) in varargs.into_iter().enumerate() {
+            context.stack[self.a + i] = value.into();
+        }
+        if self.count == Count::Unknown {
+            context.stack.set_top(self.a + amount);
+        }
+        Ok(())
     }
 }
@@ -1,10 +1,8 @@
 use instruction::*;
 use function;
-use function::{Function, NativeFunction};
-use interpreter::{CallInfo, PC};
+use function::Function;
+use interpreter::{ReturnAddress, PC, MAX_TAG_LOOP, adjust_arguments};
 use std::sync::Arc;
-use std::mem;
-use parking_lot::Mutex;
 
 // 30: JMP      A sBx   pc += sBx; if (A) close all upvalues >= R(A - 1)
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -21,12 +19,13 @@ impl LoadInstruction for Jmp {
 }
 
 impl InstructionOps for Jmp {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         if self.a != 0 {
             let upto = context.stack.get_level(self.a - 1);
             context.close_upvalues(upto);
         }
         context.ci_mut().pc += self.jump;
+        Ok(())
     }
 
     fn debug_info(&self, c: InstructionContext) -> Vec<String> {
@@ -54,7 +53,7 @@ impl LoadInstruction for Test {
 }
 
 impl InstructionOps for Test {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let jump = {
             let val = &context.stack[self.value].as_type();
             val.truethy() != self.constant
@@ -62,6 +61,7 @@ impl InstructionOps for Test {
         if jump {
             context.ci_mut().pc += 1;
         }
+        Ok(())
     }
 }
 
@@ -81,7 +81,7 @@ impl LoadInstruction for TestSet {
 }
 
 impl InstructionOps for TestSet {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let jump = {
             let value = &context.stack[self.value].as_type();
             value.truethy() != self.constant
@@ -91,9 +91,53 @@ impl InstructionOps for TestSet {
         } else {
             context.stack[self.reg] = context.stack[self.value].as_type().into();
         }
+        Ok(())
     }
 }
 
+// Collects the arguments following the function in R(A),
+// up to the top if their amount is only known at runtime.
+fn call_arguments(context: &Context, function: Reg, params: Count) -> Vec<Type> {
+    let param_start = function + 1;
+    let param_range = match params {
+        Count::Unknown => param_start..context.stack.top(),
+        Count::Known(count) => param_start..param_start + count,
+    };
+    param_range.map(|i| context.stack[i].as_type()).collect()
+}
+
+// Replaces a callee that isn't a function by its `__call` metamethod,
+// shifting the arguments up to pass the original callee first.
+fn resolve_callee(context: &mut Context, function: Reg, mut params: Count) -> LuaResult<(Function, Count)> {
+    for _ in 0..MAX_TAG_LOOP {
+        let callee = context.stack[function].as_type();
+        if let Type::Function(func) = callee {
+            return Ok((func, params))
+        }
+        let handler = callee.metamethod("__call");
+        if handler == Type::Nil {
+            return Err(context.type_error(&callee, "call", Some(Operand::Register(function))))
+        }
+        let end = match params {
+            Count::Unknown => context.stack.top(),
+            Count::Known(count) => function + 1 + count,
+        };
+        for i in (function + 1..end).rev() {
+            context.stack[i + 1] = context.stack[i].clone();
+        }
+        context.stack[function + 1] = callee.into();
+        context.stack[function] = handler.into();
+        params = match params {
+            Count::Unknown => {
+                context.stack.set_top(end + 1);
+                Count::Unknown
+            },
+            Count::Known(count) => Count::Known(count + 1),
+        };
+    }
+    Err(context.runtime_error("'__call' chain too long; possible loop"))
+}
+
 // 36: CALL     A B C   R(A), ... ,R(A+C-2) := R(A)(R(A+1), ... ,R(A+B-1))
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Call {
@@ -114,73 +158,29 @@ impl LoadInstruction for Call {
 }
 
 impl Call {
-    fn call_native(&self, context: &mut Context, native: Arc<Mutex<NativeFunction>>) {
-        let return_slots = match self.returns {
-            Count::Unknown => self.function..context.stack.top(),
-            Count::Known(count) => self.function..self.function + count,
-        };
-
-        let call_returns = {
-            let param_start = self.function + 1;
-            let params = match self.params {
-                Count::Unknown => &context.stack[param_start..context.stack.top()],
-                Count::Known(count) => &context.stack[param_start..param_start + count],
-            };
-            let mut call_info = function::FunctionInterface::new(params);
-            native.lock()(&mut call_info);
-            call_info.ret
-        };
-
-        for (item, index) in call_returns.iter().zip(return_slots) {
-            context.stack[index] = StackEntry::Type(item.clone());
-        }
-    }
-
-    fn call_lua(&self, context: &mut Context, lua: function::LuaFunction) {
-        context.ci_mut().pc += -1isize; // re-run this instruction once call has finished
-        let call_info = CallInfo::new(lua.proto.clone(), lua.upvalues.as_slice());
-        context.call_info.push(call_info);
-        let param_start = self.function + 1;
-        let param_range = match self.params {
-            Count::Unknown => param_start..context.stack.top(),
-            Count::Known(count) => param_start..param_start + count,
-        };
-        let mut params = param_range.map(|i| context.stack[i].as_type()).collect::<Vec<_>>();
-        while params.len() < lua.proto.amount_parameters as usize {
-            params.push(Type::Nil);
-        }
-        context.stack.insert_barrier();
-        for (i, param) in params.iter().enumerate() {
-            context.stack[i] = param.clone().into()
+    pub fn return_address(&self) -> ReturnAddress {
+        ReturnAddress {
+            base: self.function,
+            count: self.returns,
         }
     }
 
-    fn finish_lua_call(&self, context: &mut Context, returns: Vec<Type>) {
-        let start = self.function;
-        let range = match self.returns {
-            Count::Unknown => start..context.stack.top(),
-            Count::Known(count) => start..start + count,
-        };
-        for (i, ret) in range.zip(returns) {
-            context.stack[i] = ret.into();
-        } 
-    }
 }
 
 impl InstructionOps for Call {
-    fn exec(&self, context: &mut Context) {
-        if let Some(returns) = mem::replace(&mut context.ci_mut()._subcall_returns, None) {
-            self.finish_lua_call(context, returns);
-            return
-        }
-        if let StackEntry::Type(Type::Function(func)) = context.stack[self.function].clone() {
-            match func {
-                Function::Native(func) => self.call_native(context, func),
-                Function::Lua(func) => self.call_lua(context, func),
-            }
-        } else {
-            panic!("Call function must be of type Type::Function (got {:?})", context.stack[self.function])
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let (func, params) = resolve_callee(context, self.function, self.params)?;
+        let args = call_arguments(context, self.function, params);
+        match func {
+            Function::Native(native) => {
+                // the registers from R(A) on are dead while the native runs
+                context.stack.set_top(self.function);
+                let returns = context.call_native(&native, args)?;
+                self.return_address().store(context, returns);
+            },
+            Function::Lua(lua) => context.push_frame(&lua, args, Some(self.return_address())),
         }
+        Ok(())
     }
 }
 
@@ -204,32 +204,43 @@ impl LoadInstruction for Tailcall {
     }
 }
 
-impl InstructionOps for Tailcall {
-    fn exec(&self, context: &mut Context) {
-        let param_start = self.function + 1;
-        let param_range = match self.params {
-            Count::Unknown => param_start..context.stack.top(),
-            Count::Known(count) => param_start..param_start + count,
-        };
-        let mut params = param_range.map(|i| context.stack[i].as_type()).collect::<Vec<_>>();
-
-        if let StackEntry::Type(Type::Function(Function::Lua(func))) = context.stack[self.function].clone() {
-            while params.len() < func.proto.amount_parameters as usize {
-                params.push(Type::Nil);
-            }
-            let mut ci = context.ci_mut();
-            ci.pc = PC::new(func.proto.instructions.clone());
-            ci.func = func.proto;
-        } else {
-            panic!("Tailcall function must be of type Function::Lua (got {:?})", context.stack[self.function])
-        }
+impl Tailcall {
+    // Reuses the current frame (and its return address) for the callee,
+    // so that tail recursion runs in constant space.
+    fn replace_frame(&self, context: &mut Context, func: Arc<function::LuaFunction>, args: Vec<Type>) {
+        let (params, varargs) = adjust_arguments(&func.proto, args);
         let call_base = context.stack.get_level(0);
         context.close_upvalues(call_base);
+        {
+            let ci = context.ci_mut();
+            ci.pc = PC::new(func.proto.instructions.clone());
+            ci.func = func.proto.clone();
+            ci.upvalues = func.upvalues.clone();
+            ci.varargs = varargs;
+            ci.tail_call = true;
+        }
         context.stack.pop_barrier();
         context.stack.insert_barrier();
-        for (i, param) in params.iter().enumerate() {
-            context.stack[i] = param.clone().into()
+        for (i, param) in params.into_iter().enumerate() {
+            context.stack[i] = param.into()
         }
+        context.reset_top();
+    }
+}
+
+impl InstructionOps for Tailcall {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let (func, params) = resolve_callee(context, self.function, self.params)?;
+        let args = call_arguments(context, self.function, params);
+        match func {
+            Function::Lua(func) => self.replace_frame(context, func, args),
+            Function::Native(native) => {
+                // a native callee has no frame of its own, its results are returned directly
+                let returns = context.call_native(&native, args)?;
+                context.return_from_frame(returns);
+            },
+        }
+        Ok(())
     }
 }
 
@@ -256,26 +267,14 @@ impl LoadInstruction for Return {
 }
 
 impl InstructionOps for Return {
-    fn exec(&self, context: &mut Context) {
-        if context.call_info.pop().is_some() {
-            let return_range = match self.count {
-                Count::Unknown => {
-                    unimplemented!()
-                },
-                Count::Known(count) => {
-                    self.base..self.base + count
-                }
-            };
-            let returns: Vec<_> = return_range.map(|index| context.stack[index].as_type()).collect();
-            if !context.call_info.is_empty() {
-                let call_base = context.stack.get_level(0);
-                context.close_upvalues(call_base);
-            }
-            context.stack.pop_barrier();
-            if !context.call_info.is_empty() {
-                context.ci_mut()._subcall_returns = Some(returns)
-            }
-        }
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let return_range = match self.count {
+            Count::Unknown => self.base..context.stack.top(),
+            Count::Known(count) => self.base..self.base + count,
+        };
+        let returns: Vec<_> = return_range.map(|index| context.stack[index].as_type()).collect();
+        context.return_from_frame(returns);
+        Ok(())
     }
 
     fn debug_info(&self, _: InstructionContext) -> Vec<String> {
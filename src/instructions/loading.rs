@@ -15,9 +15,10 @@ impl LoadInstruction for Move {
 }
 
 impl InstructionOps for Move {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let val = context.stack[self.from].clone();
         context.stack[self.to] = val;
+        Ok(())
     }
 }
 
@@ -36,9 +37,10 @@ impl LoadInstruction for LoadK {
 }
 
 impl InstructionOps for LoadK {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let c = context.ci().func.constants[self.constant].clone();
         context.stack[self.local] = c.into();
+        Ok(())
     }
 
     fn debug_info(&self, c: InstructionContext) -> Vec<String> {
@@ -58,6 +60,38 @@ impl InstructionOps for LoadK {
     }
 }
 
+// 02: LOADKX   A       R(A) := Kst(extra arg)
+// The constant index is stored in the following EXTRAARG,
+// which is used when it exceeds the range of Bx.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadKx { pub local: Reg }
+
+impl LoadInstruction for LoadKx {
+    fn load(d: u32) -> Self {
+        let (a, _) = parse_A_Bx(d);
+        LoadKx {
+            local: a,
+        }
+    }
+}
+
+impl InstructionOps for LoadKx {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let constant = context.ci_mut().pc.take_extra_arg().map_err(|msg| context.runtime_error(msg))?;
+        let c = context.ci().func.constants[constant].clone();
+        context.stack[self.local] = c.into();
+        Ok(())
+    }
+
+    fn debug_info(&self, c: InstructionContext) -> Vec<String> {
+        match c.func.instructions.get(c.index + 1) {
+            Some(&Instruction::EXTRAARG(ExtraArg { value })) => c.filter(vec![
+                c.pretty_constant(DataSource::Constant(value)),
+            ]),
+            _ => vec!["missing EXTRAARG".to_owned()],
+        }
+    }
+}
 
 // 03: LOADBOOL     A B C       R(A) := (Bool)B; if (C) pc++
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -75,11 +109,12 @@ impl LoadInstruction for LoadBool {
 }
 
 impl InstructionOps for LoadBool {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         context.stack[self.reg] = Type::Boolean(self.value).into();
         if self.jump {
             context.ci_mut().pc += 1
         }
+        Ok(())
     }
     fn debug_info(&self, c: InstructionContext) -> Vec<String> {
         if let Some(local) = c.debug.locals.get(self.reg as usize) {
@@ -107,10 +142,11 @@ impl LoadInstruction for LoadNil {
 }
 
 impl InstructionOps for LoadNil {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         for i in self.start..self.start + self.range + 1 {
             context.stack[i] = (Type::Nil).into();
         }
+        Ok(())
     }
     fn debug_info(&self, c: InstructionContext) -> Vec<String> {
         let start = self.start as usize;
@@ -120,4 +156,22 @@ impl InstructionOps for LoadNil {
         }
         (start..end).map(|i| format!("{} = {}", i, c.debug.locals[i])).collect()
     }
-}
\ No newline at end of file
+}
+
+// 46: EXTRAARG     Ax          extra (larger) argument for previous opcode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtraArg { pub value: usize }
+
+impl LoadInstruction for ExtraArg {
+    fn load(d: u32) -> Self {
+        ExtraArg {
+            value: parse_Ax(d),
+        }
+    }
+}
+
+impl InstructionOps for ExtraArg {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        Err(context.runtime_error("EXTRAARG must be consumed by the preceding instruction"))
+    }
+}
@@ -1,5 +1,48 @@
 use instruction::*;
 use types::Number;
+use instructions::Call;
+
+// Numeric for loops follow the Lua 5.3 `forprep` rules:
+// if the initial value and the step are integers, the loop runs on integers
+// and a float limit is clipped to an integer. Otherwise all values are converted to floats.
+// Integer loops precompute their iteration count (stored in R(A+1)),
+// so that the counter never overflows near the integer limits.
+fn for_number(value: Type, what: &str) -> Result<Number, String> {
+    match value {
+        Type::Number(n) => Ok(n),
+        _ => Err(format!("'for' {} must be a number", what))
+    }
+}
+
+// Converts the limit of an integer loop, or returns None if the loop must not run at all.
+fn for_limit(limit: Number, init: i64, step: i64) -> Option<i64> {
+    let limit = match limit {
+        Number::Integer(limit) => limit,
+        Number::Float(limit) => {
+            let limit = if step < 0 { limit.ceil() } else { limit.floor() };
+            if limit.is_nan() {
+                return None
+            } else if limit >= 9223372036854775808.0 {
+                if step < 0 {
+                    return None
+                }
+                ::std::i64::MAX
+            } else if limit < -9223372036854775808.0 {
+                if step > 0 {
+                    return None
+                }
+                ::std::i64::MIN
+            } else {
+                limit as i64
+            }
+        }
+    };
+    if (step > 0 && init > limit) || (step < 0 && init < limit) {
+        None
+    } else {
+        Some(limit)
+    }
+}
 
 // FORLOOP,     A sBx   R(A)+=R(A+2);                                   39
 //                        if R(A) <?= R(A+1) then { pc+=sBx; R(A+3)=R(A) }
@@ -17,29 +60,62 @@ impl LoadInstruction for ForLoop {
 }
 
 impl InstructionOps for ForLoop {
-    fn exec(&self, context: &mut Context) {
-        if let (
-            Type::Number(Number::Integer(current)),
-            Type::Number(Number::Integer(limit)),
-            Type::Number(Number::Integer(step))
-        ) = (
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let next = match (
             context.stack[self.a].as_type(),
             context.stack[self.a + 1].as_type(),
             context.stack[self.a + 2].as_type()
         ) {
-            let current = current + step;
-            context.stack[self.a] = Type::Number(Number::Integer(current)).into();
-            if (step > 0 && current <= limit) || (step < 0 && current >= limit) {
-                context.stack[self.a + 3] = context.stack[self.a].as_type().into();
-                context.ci_mut().pc += self.jump;
+            (
+                Type::Number(Number::Integer(current)),
+                Type::Number(Number::Integer(remaining)),
+                Type::Number(Number::Integer(step))
+            ) => {
+                let remaining = remaining as u64;
+                if remaining > 0 {
+                    let remaining = (remaining - 1) as i64;
+                    context.stack[self.a + 1] = Type::Number(Number::Integer(remaining)).into();
+                    Some(Number::Integer(current.wrapping_add(step)))
+                } else {
+                    None
+                }
+            },
+            (Type::Number(current), Type::Number(limit), Type::Number(step)) => {
+                let (current, limit, step): (f64, f64, f64) = (current.into(), limit.into(), step.into());
+                let current = current + step;
+                if (step > 0.0 && current <= limit) || (step < 0.0 && limit <= current) {
+                    Some(Number::Float(current))
+                } else {
+                    None
+                }
+            },
+            // only reachable if the control registers were overwritten since FORPREP
+            (current, limit, step) => {
+                let msg = for_number(current, "initial value")
+                    .and(for_number(limit, "limit"))
+                    .and(for_number(step, "step"))
+                    .unwrap_err();
+                return Err(context.runtime_error(msg))
             }
-        } else {
-            panic!("invalid FORPREP types")
+        };
+        if let Some(current) = next {
+            context.stack[self.a] = Type::Number(current).into();
+            context.stack[self.a + 3] = Type::Number(current).into();
+            context.ci_mut().pc += self.jump;
         }
+        Ok(())
+    }
+
+    fn debug_info(&self, c: InstructionContext) -> Vec<String> {
+        vec![
+            format!("to [{}]", 1 + c.index as isize + self.jump + 1)
+        ]
     }
 }
 
 // FORPREP,     A sBx   R(A)-=R(A+2); pc+=sBx                           40
+// Instead of jumping to the FORLOOP, the first iteration is entered directly
+// (or the whole loop is skipped), so that R(A) never has to step below its initial value.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ForPrep { pub a: Reg, pub jump: isize }
 
@@ -53,19 +129,125 @@ impl LoadInstruction for ForPrep {
     }
 }
 
-impl InstructionOps for ForPrep {
-    fn exec(&self, context: &mut Context) {
-        if let (Type::Number(Number::Integer(val)), Type::Number(Number::Integer(add))) = (context.stack[self.a].as_type(), context.stack[self.a + 2].as_type()) {
-            context.stack[self.a] = Type::Number(Number::Integer(val - add)).into();
+impl ForPrep {
+    fn prepare(&self, context: &mut Context) -> Result<bool, String> {
+        let init = for_number(context.stack[self.a].as_type(), "initial value")?;
+        let limit = for_number(context.stack[self.a + 1].as_type(), "limit")?;
+        let step = for_number(context.stack[self.a + 2].as_type(), "step")?;
+        if let (Number::Integer(init), Number::Integer(step)) = (init, step) {
+            if step == 0 {
+                return Err("'for' step is zero".to_owned())
+            }
+            let limit = match for_limit(limit, init, step) {
+                Some(limit) => limit,
+                None => return Ok(false),
+            };
+            let remaining = if step > 0 {
+                (limit as u64).wrapping_sub(init as u64) / step as u64
+            } else {
+                (init as u64).wrapping_sub(limit as u64) / ((-(step + 1)) as u64 + 1)
+            };
+            context.stack[self.a + 1] = Type::Number(Number::Integer(remaining as i64)).into();
+            context.stack[self.a + 3] = Type::Number(Number::Integer(init)).into();
+            Ok(true)
         } else {
-            panic!("invalid FORPREP types")
+            let init: f64 = init.into();
+            let limit: f64 = limit.into();
+            let step: f64 = step.into();
+            if step == 0.0 {
+                return Err("'for' step is zero".to_owned())
+            }
+            if !((step > 0.0 && init <= limit) || (step < 0.0 && limit <= init)) {
+                return Ok(false)
+            }
+            context.stack[self.a] = Type::Number(Number::Float(init)).into();
+            context.stack[self.a + 1] = Type::Number(Number::Float(limit)).into();
+            context.stack[self.a + 2] = Type::Number(Number::Float(step)).into();
+            context.stack[self.a + 3] = Type::Number(Number::Float(init)).into();
+            Ok(true)
         }
-        context.ci_mut().pc += self.jump;
+    }
+}
+
+impl InstructionOps for ForPrep {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        if !self.prepare(context).map_err(|msg| context.runtime_error(msg))? {
+            // skip the loop body and the FORLOOP
+            context.ci_mut().pc += self.jump + 1;
+        }
+        Ok(())
+    }
+
+    fn debug_info(&self, c: InstructionContext) -> Vec<String> {
+        vec![
+            format!("to [{}]", 1 + c.index as isize + self.jump + 1)
+        ]
     }
 }
 
 // TFORCALL,    A C     R(A+3), ... ,R(A+2+C) := R(A)(R(A+1), R(A+2));  41
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TForCall { pub a: Reg, pub results: usize }
+
+impl LoadInstruction for TForCall {
+    fn load(d: u32) -> Self {
+        let (a, _, c) = parse_A_B_C(d);
+        TForCall {
+            a: a,
+            results: c,
+        }
+    }
+}
+
+impl TForCall {
+    /// The iterator call happens on a copy of the control registers,
+    /// so that R(A+3) onwards receive the results.
+    pub fn iterator_call(&self) -> Call {
+        Call {
+            function: self.a + 3,
+            params: Count::Known(2),
+            returns: Count::Known(self.results),
+        }
+    }
+}
+
+impl InstructionOps for TForCall {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        for i in 0..3 {
+            context.stack[self.a + 3 + i] = context.stack[self.a + i].clone();
+        }
+        self.iterator_call().exec(context)
+    }
+}
+
 // TFORLOOP,    A sBx  if R(A+1) ~= nil then { R(A)=R(A+1); pc += sBx } 42
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TForLoop { pub a: Reg, pub jump: isize }
 
-// SETLIST,     A B C   R(A)[(C-1)*FPF+i] := R(A+i), 1 <= i <= B        43
+impl LoadInstruction for TForLoop {
+    fn load(d: u32) -> Self {
+        let (a, b) = parse_A_sBx(d);
+        TForLoop {
+            a: a,
+            jump: b,
+        }
+    }
+}
+
+impl InstructionOps for TForLoop {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let control = context.stack[self.a + 1].as_type();
+        if control != Type::Nil {
+            context.stack[self.a] = control.into();
+            context.ci_mut().pc += self.jump;
+        }
+        Ok(())
+    }
+
+    fn debug_info(&self, c: InstructionContext) -> Vec<String> {
+        vec![
+            format!("to [{}]", 1 + c.index as isize + self.jump + 1)
+        ]
+    }
+}
 
@@ -1,4 +1,5 @@
 use instruction::*;
+use std::cmp::Ordering;
 
 macro_rules! logic {
     ($name:ident, $op:expr) => (
@@ -21,42 +22,82 @@ macro_rules! logic {
         }
 
         impl InstructionOps for $name {
-            fn exec(&self, context: &mut Context) {
+            fn exec(&self, context: &mut Context) -> LuaResult<()> {
                 let lhs = self.lhs.get_from(context);
                 let rhs = self.rhs.get_from(context);
-                let res = $op(lhs, rhs).unwrap();
-                if res == self.inverted {
+                if $op(context, lhs, rhs)? == self.inverted {
                     context.ci_mut().pc += 1
                 }
+                Ok(())
             }
         }
     )
 }
 
-fn attempted_to_compare(a: &Type, b: &Type) -> String {
-    format!("attempted to compare {} with {} ({}, {})", a.as_type_str(), b.as_type_str(), a.repr(), b.repr())
+fn attempted_to_compare(context: &Context, a: &Type, b: &Type) -> LuaError {
+    let (a, b) = (a.type_name(), b.type_name());
+    if a == b {
+        context.runtime_error(format!("attempt to compare two {} values", a))
+    } else {
+        context.runtime_error(format!("attempt to compare {} with {}", a, b))
+    }
+}
+
+// Calls the `event` metamethod of the first operand that has one, `None` if neither does.
+fn call_order_metamethod(context: &mut Context, a: &Type, b: &Type, event: &str) -> LuaResult<Option<bool>> {
+    let handler = match a.metamethod(event) {
+        Type::Nil => b.metamethod(event),
+        handler => handler,
+    };
+    if handler == Type::Nil {
+        return Ok(None)
+    }
+    let result = context.call(handler, vec![a.clone(), b.clone()])?;
+    Ok(Some(result.first().map_or(false, Type::truethy)))
 }
 
+// Orders numbers exactly (even across integers and floats) and strings byte-wise.
+fn primitive_order(a: &Type, b: &Type) -> Option<Option<Ordering>> {
+    match (a, b) {
+        (&Type::Number(ref a), &Type::Number(ref b)) => Some(a.partial_cmp(b)),
+        (&Type::String(ref a), &Type::String(ref b)) => Some(Some(a.as_bytes().cmp(b.as_bytes()))),
+        _ => None,
+    }
+}
 
 // 31: EQ       A B C   if ((RK(B) == RK(C)) ~= A) then pc++
-logic!(Equals, |a, b| -> Result<bool, String> {
-    Ok(a == b)
+logic!(Equals, |context: &mut Context, a: Type, b: Type| -> LuaResult<bool> {
+    if a == b {
+        return Ok(true)
+    }
+    match (&a, &b) {
+        (&Type::Table(_), &Type::Table(_))
+        | (&Type::UserData(_), &Type::UserData(_)) => {
+            Ok(call_order_metamethod(context, &a, &b, "__eq")?.unwrap_or(false))
+        },
+        _ => Ok(false),
+    }
 });
 
 // 32: LT       A B C   if ((RK(B) <  RK(C)) ~= A) then pc++
-logic!(LessThan, |a, b| {
-    if let (&Type::Number(ref a_num), &Type::Number(ref b_num)) = (&a, &b) {
-        Ok(a_num < b_num)
-    } else {
-        Err(attempted_to_compare(&a, &b))
+logic!(LessThan, |context: &mut Context, a: Type, b: Type| -> LuaResult<bool> {
+    match primitive_order(&a, &b) {
+        Some(order) => Ok(order == Some(Ordering::Less)),
+        None => call_order_metamethod(context, &a, &b, "__lt")?
+            .ok_or_else(|| attempted_to_compare(context, &a, &b)),
     }
 });
 
 // 33: LE       A B C   if ((RK(B) <= RK(C)) ~= A) then pc++
-logic!(LessThanOrEquals, |a, b| {
-    if let (&Type::Number(ref a_num), &Type::Number(ref b_num)) = (&a, &b) {
-        Ok(a_num <= b_num)
-    } else {
-        Err(attempted_to_compare(&a, &b))
+logic!(LessThanOrEquals, |context: &mut Context, a: Type, b: Type| -> LuaResult<bool> {
+    if let Some(order) = primitive_order(&a, &b) {
+        return Ok(order == Some(Ordering::Less) || order == Some(Ordering::Equal))
+    }
+    if let Some(le) = call_order_metamethod(context, &a, &b, "__le")? {
+        return Ok(le)
     }
+    // without __le, a <= b is evaluated as not (b < a)
+    call_order_metamethod(context, &b, &a, "__lt")?
+        .map(|lt| !lt)
+        .ok_or_else(|| attempted_to_compare(context, &a, &b))
 });
@@ -1,6 +1,6 @@
 use instruction::*;
+use types::Number;
 use table::LuaTable;
-use std::collections::btree_map::Entry;
 
 // GETTABLE,    A B C   R(A) := R(B)[RK(C)]                             07
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -17,21 +17,12 @@ impl LoadInstruction for GetTable {
 }
 
 impl InstructionOps for GetTable {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let key = self.c.get_from(context);
-        let _table = context.stack[self.b].as_type();
-        let table = as_type_variant!(_table, Type::Table);
-        let mut _guard = table.lock();
-        let entry = _guard.entry(key.clone()); // this is pretty stupid...
-        let value = if entry.key() == &key {
-            match entry {
-                Entry::Occupied(e) => e.get().clone(),
-                Entry::Vacant(_) => Type::Nil,
-            }
-        } else {
-            Type::Nil
-        };
-        context.stack[self.a] = value.clone().into();
+        let table = context.stack[self.b].as_type();
+        let value = context.index(table, key, Operand::Register(self.b))?;
+        context.stack[self.a] = value.into();
+        Ok(())
     }
 }
 
@@ -50,34 +41,48 @@ impl LoadInstruction for SetTable {
 }
 
 impl InstructionOps for SetTable {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let key = self.b.get_from(context);
         let value = self.c.get_from(context);
-        let _table = context.stack[self.a].as_type();
-        let table = as_type_variant!(_table, Type::Table);
-        let mut _guard = table.lock();
-        _guard.insert(key, value);
+        let table = context.stack[self.a].as_type();
+        context.new_index(table, key, value, Operand::Register(self.a))?;
+        Ok(())
     }
 }
 
 // NEWTABLE,    A B C   R(A) := {} (size = B,C)                         11
+// B and C are encoded as "floating point bytes" (eeeeexxx), see `fb2int`.
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct NewTable { pub a: Reg }
+pub struct NewTable { pub a: Reg, pub array_size: usize, pub hash_size: usize }
+
+// Decodes a "floating point byte": (1xxx) * 2^(eeeee - 1) if eeeee != 0, xxx otherwise.
+fn fb2int(x: usize) -> usize {
+    if x < 8 {
+        x
+    } else {
+        ((x & 7) + 8) << ((x >> 3) - 1)
+    }
+}
+
 impl LoadInstruction for NewTable {
     fn load(d: u32) -> Self {
-        let (a, _, _) = parse_A_B_C(d);
+        let (a, b, c) = parse_A_B_C(d);
         NewTable {
             a: a,
-            // TODO: actually use b and c
+            array_size: fb2int(b),
+            hash_size: fb2int(c),
         }
     }
 }
 
 impl InstructionOps for NewTable {
-    fn exec(&self, context: &mut Context) {
-        let table = LuaTable::new();
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let table = LuaTable::with_capacity(self.array_size, self.hash_size);
         let as_type = Type::Table(table);
+        context.track(&as_type);
         context.stack[self.a] = as_type.into();
+        context.check_gc();
+        Ok(())
     }
 }
 
@@ -100,15 +105,59 @@ impl LoadInstruction for SelfOp {
 }
 
 impl InstructionOps for SelfOp {
-    fn exec(&self, context: &mut Context) {
-        let instance = context.stack[self.table].clone();
-        context.stack[self.a + 1] = instance.clone();
-        
-        let table = as_type_variant!(instance.as_type(), Type::Table);
-        let mut _guard = table.lock();
-        let func = _guard.get(&self.key.get_from(context))
-            .cloned()
-            .unwrap_or(Type::Nil);
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let key = self.key.get_from(context);
+        let instance = context.stack[self.table].as_type();
+        context.stack[self.a + 1] = instance.clone().into();
+        let func = context.index(instance, key, Operand::Register(self.table))?;
         context.stack[self.a] = func.into();
+        Ok(())
     }
-}
\ No newline at end of file
+}
+// SETLIST,     A B C   R(A)[(C-1)*FPF+i] := R(A+i), 1 <= i <= B        43
+// if (B == 0) then set up to 'top'.
+// if (C == 0) then the batch number is stored in the following EXTRAARG.
+pub const FIELDS_PER_FLUSH: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetList { pub a: Reg, pub count: Count, pub batch: usize }
+impl LoadInstruction for SetList {
+    fn load(d: u32) -> Self {
+        let (a, b, c) = parse_A_B_C(d);
+        SetList {
+            a: a,
+            count: match b {
+                0 => Count::Unknown,
+                b => Count::Known(b),
+            },
+            batch: c,
+        }
+    }
+}
+
+impl InstructionOps for SetList {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let batch = match self.batch {
+            0 => context.ci_mut().pc.take_extra_arg().map_err(|msg| context.runtime_error(msg))?,
+            c => c,
+        };
+        let count = match self.count {
+            Count::Unknown => context.stack.top() - (self.a + 1),
+            Count::Known(count) => count,
+        };
+        let offset = (batch - 1) * FIELDS_PER_FLUSH;
+        let table = match context.stack[self.a].as_type() {
+            Type::Table(table) => table,
+            other => return Err(context.runtime_error(format!("SETLIST expects a table, got a {} value", other.type_name()))),
+        };
+        let mut _guard = table.lock();
+        for i in 1..count + 1 {
+            let key = Type::Number(Number::Integer((offset + i) as i64));
+            _guard.insert(key, context.stack[self.a + i].as_type());
+        }
+        if self.count == Count::Unknown {
+            context.reset_top();
+        }
+        Ok(())
+    }
+}
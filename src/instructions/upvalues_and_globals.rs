@@ -16,9 +16,10 @@ impl LoadInstruction for GetUpval {
 }
 
 impl InstructionOps for GetUpval {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let upval = context.ci().upvalues[self.upvalue].clone();
         context.stack[self.reg] = upval.value(context).into();
+        Ok(())
     }
 }
 
@@ -38,20 +39,12 @@ impl LoadInstruction for GetTabUp {
 }
 
 impl InstructionOps for GetTabUp {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let key = self.constant.get_from(context);
-        let value = {
-            let table = context.ci().upvalues[self.upvalue].value(context);
-            if let Type::Table(ref upvalue) = table {
-                let table = upvalue.lock();
-                table.get(&key)
-                    .cloned()
-                    .unwrap_or(Type::Nil)
-            } else {
-                panic!("GetTabUp upvalue must be of type Type::Table (got {:?})", table)
-            }
-        };
+        let table = context.ci().upvalues[self.upvalue].value(context);
+        let value = context.index(table, key, Operand::Upvalue(self.upvalue))?;
         context.stack[self.reg] = value.into();
+        Ok(())
     }
     fn debug_info(&self, c: InstructionContext) -> Vec<String> {
         c.filter(vec![
@@ -83,11 +76,39 @@ impl LoadInstruction for SetTabUp {
 }
 
 impl InstructionOps for SetTabUp {
-    fn exec(&self, context: &mut Context) {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
         let upval = context.ci().upvalues[self.upval].clone();
         let key = self.key.get_from(context);
         let value = self.value.get_from(context);
-        let table = as_type_variant!(upval.value(context), Type::Table);
-        table.lock().insert(key, value);
+        let table = upval.value(context);
+        context.new_index(table, key, value, Operand::Upvalue(self.upval))?;
+        Ok(())
     }
-}
\ No newline at end of file
+}
+// 09: SETUPVAL   A B     UpValue[B] := R(A)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetUpval { pub reg: Reg, pub upvalue: usize }
+
+impl LoadInstruction for SetUpval {
+    fn load(d: u32) -> Self {
+        let (a, b) = parse_A_B(d);
+        SetUpval {
+            reg: a,
+            upvalue: b,
+        }
+    }
+}
+
+impl InstructionOps for SetUpval {
+    fn exec(&self, context: &mut Context) -> LuaResult<()> {
+        let upval = context.ci().upvalues[self.upvalue].clone();
+        let value = context.stack[self.reg].as_type();
+        upval.set(context, value);
+        Ok(())
+    }
+    fn debug_info(&self, c: InstructionContext) -> Vec<String> {
+        c.filter(vec![
+            c.pretty_upval(self.upvalue),
+        ])
+    }
+}
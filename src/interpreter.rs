@@ -1,11 +1,29 @@
-use instruction::Instruction;
+use instruction::{Instruction, Reg, Count, Operand};
+use instructions::ExtraArg;
 use bytecode::Bytecode;
 use function_block::FunctionBlock;
 use env::Environment;
 use types::Type;
-use stack::{Stack, StackLevel};
+use stack::{Stack, StackLevel, StackEntry};
 use std::ops::AddAssign;
+use std::mem;
+use std::fmt;
+use std::thread;
+use std::sync::Arc;
 use upvalues::{Upvalue, SharedUpvalue};
+use function::{Function, LuaFunction, NativeFunction, NativeClosure};
+use coroutine::{LuaThread, Status};
+use string::LuaString;
+use table::check_key;
+use gc::{Heap, Object};
+use error::{LuaError, LuaResult};
+
+/// Upper bound for `__index` and `__newindex` chains, longer chains are most likely loops.
+pub const MAX_TAG_LOOP: usize = 2000;
+
+// Tracebacks of deep call stacks only list this many innermost and outermost functions.
+const TRACEBACK_HEAD: usize = 10;
+const TRACEBACK_TAIL: usize = 11;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct PC {
@@ -33,6 +51,17 @@ impl PC {
     }
     pub fn current(&self) -> &Instruction { self.get(0) }
     pub fn skip(&mut self, n: usize) { *self += n as isize }
+
+    /// Consumes the EXTRAARG that follows the instruction currently being executed.
+    pub fn take_extra_arg(&mut self) -> Result<usize, String> {
+        let value = match self._instructions.get(self._pc) {
+            Some(&Instruction::EXTRAARG(ExtraArg { value })) => value,
+            Some(other) => return Err(format!("expected EXTRAARG after {:?}, got {:?}", self.get(-1), other)),
+            None => return Err(format!("expected EXTRAARG after {:?}", self.get(-1))),
+        };
+        self.skip(1);
+        Ok(value)
+    }
 }
 
 impl AddAssign<isize> for PC {
@@ -41,16 +70,45 @@ impl AddAssign<isize> for PC {
     }
 }
 
+#[derive(Debug)]
 pub struct RunResult {
     instruction_count: usize
 }
 
+/// Registers of the calling frame that receive the results of a call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReturnAddress {
+    pub base: Reg,
+    pub count: Count,
+}
+
+impl ReturnAddress {
+    /// Stores `values` in the current frame, padding fixed result counts with nil
+    /// and moving the top past the last value for `Count::Unknown`.
+    pub fn store(&self, context: &mut Context, mut values: Vec<Type>) {
+        if let Count::Known(count) = self.count {
+            values.resize(count, Type::Nil);
+        }
+        let amount = values.len();
+        for (i, value) in values.into_iter().enumerate() {
+            context.stack[self.base + i] = value.into();
+        }
+        match self.count {
+            Count::Unknown => context.stack.set_top(self.base + amount),
+            Count::Known(_) => context.reset_top(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CallInfo {
     pub pc: PC,
     pub func: FunctionBlock,
     pub upvalues: Vec<SharedUpvalue>,
-    pub _subcall_returns: Option<Vec<Type>>,
+    pub varargs: Vec<Type>,
+    pub ret: Option<ReturnAddress>,
+    // whether the frame was reused for a tail call, which hides the original callee
+    pub tail_call: bool,
 }
 
 impl CallInfo {
@@ -59,26 +117,752 @@ impl CallInfo {
             pc: PC::new(func.instructions.clone()),
             upvalues: upvalues.into(),
             func: func,
-            _subcall_returns: None
+            varargs: vec![],
+            ret: None,
+            tail_call: false,
+        }
+    }
+
+    /// The line of the instruction being executed, if debug information is present.
+    pub fn current_line(&self) -> Option<u32> {
+        self.func.line(self.pc._pc.saturating_sub(1))
+    }
+}
+
+// Truncates or pads the arguments to the amount of fixed parameters,
+// keeping the surplus as varargs if the prototype is variadic.
+pub fn adjust_arguments(proto: &FunctionBlock, mut params: Vec<Type>) -> (Vec<Type>, Vec<Type>) {
+    let fixed = proto.amount_parameters as usize;
+    let surplus = if params.len() > fixed {
+        params.split_off(fixed)
+    } else {
+        vec![]
+    };
+    params.resize(fixed, Type::Nil);
+    let varargs = if proto.is_vararg { surplus } else { vec![] };
+    (params, varargs)
+}
+
+// Describes a function for tracebacks like `pushfuncname`. Unless its frame was reused
+// for a tail call, the instruction of the calling Lua function tells how it was referred to.
+fn function_name(frame: Option<&CallInfo>, caller: Option<&CallInfo>) -> String {
+    let name = match (frame, caller) {
+        (Some(ci), _) if ci.tail_call => None,
+        (_, Some(caller)) => caller.func.call_name(caller.pc._pc - 1),
+        _ => None,
+    };
+    match (name, frame) {
+        // the reference implementation names global functions after their entry in `_G`
+        (Some(("global", name)), _) => format!("function '{}'", name),
+        (Some((kind, name)), _) => format!("{} '{}'", kind, name),
+        (None, Some(ci)) if ci.func.lines.0 == 0 => "main chunk".to_owned(),
+        (None, Some(ci)) => format!("function <{}:{}>", ci.func.short_source(), ci.func.lines.0),
+        (None, None) => "?".to_owned(),
+    }
+}
+
+// Objects referred to by the stack, the frames, the pending results and the open upvalues of a thread.
+fn thread_references(stack: &Stack, call_info: &[CallInfo], returned: &[Type], open_upval: &SharedUpvalue) -> Vec<Object> {
+    let mut references: Vec<Object> = stack.entries()
+        .iter()
+        .filter_map(|entry| match *entry {
+            StackEntry::Type(ref value) => Object::from_value(value),
+            StackEntry::ClosureBarrier => None,
+        })
+        .collect();
+    for ci in call_info {
+        references.extend(ci.upvalues.iter().map(SharedUpvalue::as_object));
+        references.extend(ci.varargs.iter().filter_map(Object::from_value));
+    }
+    references.extend(returned.iter().filter_map(Object::from_value));
+    references.push(open_upval.as_object());
+    references
+}
+
+/// `true` followed by the results of a successful protected call, `false` and the error object otherwise.
+pub fn protected_returns(result: LuaResult<Vec<Type>>) -> Vec<Type> {
+    match result {
+        Ok(mut returns) => {
+            returns.insert(0, Type::Boolean(true));
+            returns
+        },
+        Err(err) => vec![Type::Boolean(false), err.value],
+    }
+}
+
+// A call made through `call_yieldable` that yielded and waits for its callee to complete.
+#[derive(Clone)]
+struct PendingCall {
+    // amount of frames below the callee's
+    depth: usize,
+    continuation: Arc<NativeClosure>,
+    // protected calls restore the enclosing message handler once they complete
+    protected: bool,
+    previous_handler: Option<Type>,
+}
+
+impl fmt::Debug for PendingCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PendingCall {{ depth: {}, protected: {} }}", self.depth, self.protected)
+    }
+}
+
+/// The part of a `Context` that belongs to a single thread. A coroutine keeps its state
+/// while it is suspended, resuming it swaps the state with the context's.
+#[derive(Debug, Clone)]
+pub struct ThreadState {
+    call_info: Vec<CallInfo>,
+    pub stack: Stack,
+    open_upval: SharedUpvalue,
+    returned: Vec<Type>,
+    native_calls: Vec<usize>,
+    error_handler: Option<Type>,
+    protected_calls: usize,
+    non_yieldable: usize,
+    pending_calls: Vec<PendingCall>,
+}
+
+impl ThreadState {
+    pub fn new() -> Self {
+        ThreadState {
+            call_info: vec![],
+            stack: Stack::new(),
+            open_upval: SharedUpvalue::new(Upvalue::Closed(Type::Nil)),
+            returned: vec![],
+            native_calls: vec![],
+            error_handler: None,
+            // errors end up at `resume` instead of stopping the program
+            protected_calls: 1,
+            non_yieldable: 0,
+            pending_calls: vec![],
         }
     }
+
+    /// The objects the thread refers to, for the garbage collector.
+    pub fn references(&self) -> Vec<Object> {
+        thread_references(&self.stack, &self.call_info, &self.returned, &self.open_upval)
+    }
+}
+
+impl Default for ThreadState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Context {
     pub call_info: Vec<CallInfo>,
     pub stack: Stack,
-    open_upval: SharedUpvalue
+    open_upval: SharedUpvalue,
+    // results of the last frame without a return address, picked up by `call`
+    returned: Vec<Type>,
+    // the objects created by this context, for the cycle collector
+    heap: Heap,
+    // objects whose metatable had a `__gc` field when it was set, in that order
+    finalizers: Vec<Object>,
+    // for each running native function, the amount of Lua frames below it
+    native_calls: Vec<usize>,
+    // message handler of the innermost `xpcall`, taken once it has seen an error
+    error_handler: Option<Type>,
+    // amount of running protected calls, errors outside of them get a traceback
+    protected_calls: usize,
+    // amount of running calls that can't be suspended, like `nny`
+    non_yieldable: usize,
+    // calls of native functions that yielded, innermost last
+    pending_calls: Vec<PendingCall>,
+    // the running thread, the state above is its own
+    thread: LuaThread,
+    main_thread: LuaThread,
+    // values passed to `coroutine.yield` while the yield returns to `resume`
+    yielded: Option<Vec<Type>>,
+    // where native functions suspended by that yield go in `pending_calls`
+    yield_mark: usize,
 }
 
 impl Context {
     pub fn new(stack: &Stack) -> Self {
+        let main_thread = LuaThread::main();
         Context {
             call_info: vec![],
             stack: stack.clone(),
-            open_upval: SharedUpvalue::new(Upvalue::Closed(Type::Nil))
+            open_upval: SharedUpvalue::new(Upvalue::Closed(Type::Nil)),
+            returned: vec![],
+            heap: Heap::new(),
+            finalizers: vec![],
+            native_calls: vec![],
+            error_handler: None,
+            protected_calls: 0,
+            // the main thread can't yield
+            non_yieldable: 1,
+            pending_calls: vec![],
+            thread: main_thread.clone(),
+            main_thread: main_thread,
+            yielded: None,
+            yield_mark: 0,
         }
     }
+
+    // Exchanges the running thread's state with `state`.
+    fn swap_thread(&mut self, state: &mut ThreadState) {
+        mem::swap(&mut self.call_info, &mut state.call_info);
+        mem::swap(&mut self.stack, &mut state.stack);
+        mem::swap(&mut self.open_upval, &mut state.open_upval);
+        mem::swap(&mut self.returned, &mut state.returned);
+        mem::swap(&mut self.native_calls, &mut state.native_calls);
+        mem::swap(&mut self.error_handler, &mut state.error_handler);
+        mem::swap(&mut self.protected_calls, &mut state.protected_calls);
+        mem::swap(&mut self.non_yieldable, &mut state.non_yieldable);
+        mem::swap(&mut self.pending_calls, &mut state.pending_calls);
+    }
+
+    /// Whether `thread` is the running thread, whose stack is the context's.
+    pub fn is_running(&self, thread: &LuaThread) -> bool {
+        self.thread == *thread
+    }
+
+    /// The running thread and whether it is the main thread, like `coroutine.running`.
+    pub fn running(&self) -> (LuaThread, bool) {
+        (self.thread.clone(), self.thread == self.main_thread)
+    }
+
+    /// Whether the running function may yield, i.e. it runs in a coroutine
+    /// and all native functions below it allow yielding.
+    pub fn is_yieldable(&self) -> bool {
+        self.non_yieldable == 0
+    }
+
+    // Whether the error being passed on is a yield returning to `resume`.
+    fn is_yielding(&self) -> bool {
+        self.yielded.is_some()
+    }
+
+    pub fn step(&mut self) -> LuaResult<()> {
+        let instruction = *self.ci().pc.current();
+        self.ci_mut().pc += 1;
+        instruction.exec(self)
+    }
+
+    /// Enters a Lua function. `ret` receives its results in the calling frame,
+    /// without one they are left for `call` to collect.
+    pub fn push_frame(&mut self, func: &LuaFunction, args: Vec<Type>, ret: Option<ReturnAddress>) {
+        let (params, varargs) = adjust_arguments(&func.proto, args);
+        let mut call_info = CallInfo::new(func.proto.clone(), func.upvalues.as_slice());
+        call_info.varargs = varargs;
+        call_info.ret = ret;
+        self.call_info.push(call_info);
+        self.stack.insert_barrier();
+        for (i, param) in params.into_iter().enumerate() {
+            self.stack[i] = param.into()
+        }
+        self.reset_top();
+    }
+
+    /// Runs a native function, it doesn't get a frame of its own.
+    pub fn call_native(&mut self, native: &NativeClosure, args: Vec<Type>) -> LuaResult<Vec<Type>> {
+        self.native_calls.push(self.call_info.len());
+        let result = native.call(self, args);
+        // the message handler runs while the failing function is still on the call stack
+        let result = match result {
+            Err(err) if !self.is_yielding() => Err(self.handle_error(err)),
+            result => result,
+        };
+        self.native_calls.pop();
+        result
+    }
+
+    /// Calls `func` from within an instruction (e.g. a metamethod) and runs it to completion.
+    /// On errors, the frames it entered are unwound before the error is passed on.
+    /// The callee can't yield, see `call_yieldable`.
+    pub fn call(&mut self, func: Type, args: Vec<Type>) -> LuaResult<Vec<Type>> {
+        self.non_yieldable += 1;
+        let result = self.call_function(func, args);
+        self.non_yieldable -= 1;
+        result
+    }
+
+    // Runs `func` until it returns or yields. The frames of a yielding callee stay in place.
+    fn call_function(&mut self, func: Type, mut args: Vec<Type>) -> LuaResult<Vec<Type>> {
+        match func {
+            Type::Function(Function::Native(native)) => self.call_native(&native, args),
+            Type::Function(Function::Lua(lua)) => {
+                let top = self.stack.top();
+                let depth = self.call_info.len();
+                self.push_frame(&lua, args, None);
+                while self.call_info.len() > depth {
+                    if let Err(err) = self.step() {
+                        if self.is_yielding() {
+                            return Err(err)
+                        }
+                        let err = self.handle_error(err);
+                        self.unwind(depth);
+                        self.stack.set_top(top);
+                        return Err(err)
+                    }
+                }
+                self.stack.set_top(top);
+                Ok(mem::replace(&mut self.returned, vec![]))
+            },
+            other => match other.metamethod("__call") {
+                Type::Nil => Err(self.type_error(&other, "call", None)),
+                handler => {
+                    args.insert(0, other);
+                    self.call_function(handler, args)
+                },
+            },
+        }
+    }
+
+    /// Like `lua_callk`, calls `func` from a native function such that the callee may yield.
+    /// The native function returns what this returns: the results of `continuation`, which is called
+    /// with the callee's results. If the callee yields, the yield is returned instead and
+    /// the continuation runs once the callee returned after the coroutine was resumed.
+    pub fn call_yieldable(&mut self, func: Type, args: Vec<Type>, continuation: NativeFunction) -> LuaResult<Vec<Type>> {
+        let depth = self.call_info.len();
+        let result = self.call_function(func, args);
+        if result.is_err() && self.is_yielding() {
+            self.suspend_call(PendingCall {
+                depth: depth,
+                continuation: Arc::new(continuation.into()),
+                protected: false,
+                previous_handler: None,
+            });
+            return result
+        }
+        self.call_native(&continuation.into(), result?)
+    }
+
+    /// Like `lua_pcallk`, `protected_call` for native functions that let the callee yield,
+    /// see `call_yieldable`. The call stays protected until it completes,
+    /// `continuation` gets its outcome like the results of `pcall`.
+    pub fn protected_call_yieldable(&mut self, func: Type, args: Vec<Type>, handler: Option<Type>, continuation: NativeFunction) -> LuaResult<Vec<Type>> {
+        let previous = mem::replace(&mut self.error_handler, handler);
+        self.protected_calls += 1;
+        let depth = self.call_info.len();
+        let result = self.call_function(func, args);
+        if result.is_err() && self.is_yielding() {
+            self.suspend_call(PendingCall {
+                depth: depth,
+                continuation: Arc::new(continuation.into()),
+                protected: true,
+                previous_handler: previous,
+            });
+            return result
+        }
+        let result = result.map_err(|err| self.handle_error(err));
+        self.protected_calls -= 1;
+        self.error_handler = previous;
+        self.call_native(&continuation.into(), protected_returns(result))
+    }
+
+    // Keeps a call for `resume` while a yield passes through. Calls made further out come later,
+    // so each goes below the ones suspended before it.
+    fn suspend_call(&mut self, pending: PendingCall) {
+        self.pending_calls.insert(self.yield_mark, pending);
+    }
+
+    // Hands the outcome of a pending call to the native function that made it.
+    fn finish_pending(&mut self, pending: PendingCall, result: LuaResult<Vec<Type>>) -> LuaResult<Vec<Type>> {
+        let PendingCall { continuation, protected, previous_handler, .. } = pending;
+        let args = if protected {
+            self.protected_calls -= 1;
+            self.error_handler = previous_handler;
+            protected_returns(result)
+        } else {
+            result?
+        };
+        self.call_native(&continuation, args)
+    }
+
+    /// Like `lua_yield`, suspends the running coroutine, handing `values` to `resume`.
+    /// Native functions return the error this gives, which either tells why the coroutine
+    /// can't yield or carries the yield to `resume`. Once the coroutine is resumed,
+    /// the native function returns the values passed to `resume`.
+    pub fn yield_values(&mut self, values: Vec<Type>) -> LuaError {
+        if !self.is_yieldable() {
+            return if self.thread == self.main_thread {
+                "attempt to yield from outside a coroutine".into()
+            } else {
+                "attempt to yield across a C-call boundary".into()
+            }
+        }
+        self.yielded = Some(values);
+        self.yield_mark = self.pending_calls.len();
+        LuaError::new(Type::Nil)
+    }
+
+    /// Like `lua_resume`, runs the coroutine `thread` until it yields, returns or fails.
+    /// `args` are passed to its body on the first resume and returned by `coroutine.yield` afterwards.
+    /// Yielded and returned values are handed back alike, the status of the coroutine tells them apart.
+    pub fn resume(&mut self, thread: &LuaThread, args: Vec<Type>) -> LuaResult<Vec<Type>> {
+        let (mut state, body) = {
+            let mut coroutine = thread.lock();
+            match coroutine.status {
+                Status::Suspended => {},
+                Status::Dead => return Err("cannot resume dead coroutine".into()),
+                Status::Running | Status::Normal => return Err("cannot resume non-suspended coroutine".into()),
+            }
+            coroutine.status = Status::Running;
+            (coroutine.state.take().unwrap_or_default(), coroutine.body.take())
+        };
+        self.swap_thread(&mut state);
+        let resumer = mem::replace(&mut self.thread, thread.clone());
+        {
+            let mut parked = resumer.lock();
+            parked.status = Status::Normal;
+            parked.state = Some(state);
+        }
+
+        let result = self.run_thread(body, args);
+        let (result, status) = match (result, self.yielded.take()) {
+            (Err(_), Some(values)) => (Ok(values), Status::Suspended),
+            (result, _) => (result, Status::Dead),
+        };
+
+        let mut state = {
+            let mut parked = resumer.lock();
+            parked.status = Status::Running;
+            parked.state.take().unwrap_or_default()
+        };
+        self.swap_thread(&mut state);
+        self.thread = resumer;
+        let mut coroutine = thread.lock();
+        coroutine.status = status;
+        // dead coroutines don't need their stack anymore
+        if status == Status::Suspended {
+            coroutine.state = Some(state);
+        }
+        result
+    }
+
+    // Runs the swapped in coroutine until it yields (with `yielded` set), returns or fails.
+    // The first resume starts `body`, later ones pass `args` to the call the coroutine yielded in.
+    fn run_thread(&mut self, body: Option<Type>, args: Vec<Type>) -> LuaResult<Vec<Type>> {
+        // the outcome of the innermost call waiting to be completed
+        let mut outcome = match body {
+            Some(Type::Function(Function::Lua(lua))) => {
+                self.push_frame(&lua, args, None);
+                None
+            },
+            Some(body) => Some(self.call_function(body, args)),
+            None => Some(Ok(args)),
+        };
+        loop {
+            let result = match outcome.take() {
+                Some(result) => result,
+                None => {
+                    // the callee of the innermost pending call (or the body) returned
+                    let depth = self.pending_calls.last().map_or(0, |pending| pending.depth);
+                    if self.call_info.len() == depth {
+                        Ok(mem::replace(&mut self.returned, vec![]))
+                    } else {
+                        match self.step() {
+                            Ok(()) => continue,
+                            Err(err) => if self.is_yielding() { Err(err) } else { Err(self.handle_error(err)) },
+                        }
+                    }
+                },
+            };
+            if result.is_err() && self.is_yielding() {
+                return result
+            }
+            match result {
+                Ok(values) => {
+                    let depth = self.pending_calls.last().map(|pending| pending.depth);
+                    if depth == Some(self.call_info.len()) {
+                        let pending = self.pending_calls.pop().unwrap();
+                        outcome = Some(self.finish_pending(pending, Ok(values)));
+                    } else if self.call_info.is_empty() {
+                        return Ok(values)
+                    } else if let Err(err) = self.finish_call(values) {
+                        outcome = Some(Err(self.handle_error(err)));
+                    }
+                },
+                // the innermost pending call gets the error, the frames of its callee are gone
+                Err(err) => match self.pending_calls.pop() {
+                    Some(pending) => {
+                        self.unwind(pending.depth);
+                        outcome = Some(self.finish_pending(pending, Err(err)));
+                    },
+                    None => {
+                        self.unwind(0);
+                        return Err(err)
+                    },
+                },
+            }
+        }
+    }
+
+    // Stores the results of a native function that yielded, as the call instruction of the running frame would have.
+    // Only calls can be completed, other instructions only call natives that can't yield.
+    fn finish_call(&mut self, values: Vec<Type>) -> LuaResult<()> {
+        match *self.ci().pc.get(-1) {
+            Instruction::CALL(call) => call.return_address().store(self, values),
+            Instruction::TFORCALL(tforcall) => tforcall.iterator_call().return_address().store(self, values),
+            Instruction::TAILCALL(_) => self.return_from_frame(values),
+            _ => return Err("attempt to yield across a C-call boundary".into()),
+        }
+        Ok(())
+    }
+
+    /// Calls `func` like `call`, with `handler` as the message handler for errors raised within.
+    /// The handler of an enclosing protected call is restored afterwards.
+    pub fn protected_call(&mut self, func: Type, args: Vec<Type>, handler: Option<Type>) -> LuaResult<Vec<Type>> {
+        let previous = mem::replace(&mut self.error_handler, handler);
+        self.protected_calls += 1;
+        let result = match self.call(func, args) {
+            Err(err) => Err(self.handle_error(err)),
+            ok => ok,
+        };
+        self.protected_calls -= 1;
+        self.error_handler = previous;
+        result
+    }
+
+    // Passes an error to the message handler, unless there is none or it already saw the error.
+    // Errors outside of protected calls get a traceback instead.
+    // The frames that raised the error are still in place while either runs.
+    fn handle_error(&mut self, mut err: LuaError) -> LuaError {
+        if self.protected_calls == 0 {
+            if err.traceback.is_none() {
+                err.traceback = Some(self.traceback(0));
+            }
+            return err
+        }
+        match self.error_handler.take() {
+            None => err,
+            Some(handler) => match self.call(handler, vec![err.value]) {
+                Ok(values) => LuaError::new(values.into_iter().next().unwrap_or(Type::Nil)),
+                Err(_) => LuaError::new("error in error handling"),
+            },
+        }
+    }
+
+    // The running functions, innermost first. Native functions don't have
+    // a `CallInfo` and are listed as `None`.
+    fn frames(&self) -> Vec<Option<&CallInfo>> {
+        let mut natives = self.native_calls.iter().rev().peekable();
+        let mut frames = vec![];
+        for i in (0..self.call_info.len()).rev() {
+            while natives.peek().map_or(false, |&&depth| depth > i) {
+                natives.next();
+                frames.push(None);
+            }
+            frames.push(Some(&self.call_info[i]));
+        }
+        frames.extend(natives.map(|_| None));
+        frames
+    }
+
+    /// Like `luaL_where`, the "file:line: " of the function at `level` of the call stack
+    /// (0 being the running function). Empty for native functions and without debug information.
+    pub fn position(&self, level: usize) -> String {
+        match self.frames().get(level) {
+            Some(&Some(ci)) => match ci.current_line() {
+                Some(line) => format!("{}:{}: ", ci.func.short_source(), line),
+                None => String::new(),
+            },
+            _ => String::new(),
+        }
+    }
+
+    /// Like `luaG_runerror`, an error whose message starts with the position of the running Lua function.
+    pub fn runtime_error<T: Into<String>>(&self, message: T) -> LuaError {
+        LuaError::new(format!("{}{}", self.position(0), message.into()))
+    }
+
+    /// Like `luaG_typeerror`, an error for attempting `action` on `value`, which was read
+    /// from `operand` of the running instruction if given.
+    pub fn type_error(&self, value: &Type, action: &str, operand: Option<Operand>) -> LuaError {
+        let info = operand.map_or_else(String::new, |operand| self.variable_info(operand));
+        self.runtime_error(format!("attempt to {} a {} value{}", action, value.type_name(), info))
+    }
+
+    /// Like `varinfo`, names the variable behind `operand` of the running instruction,
+    /// e.g. " (local 'cfg')". Empty if it can't be named.
+    pub fn variable_info(&self, operand: Operand) -> String {
+        let ci = match self.frames().first() {
+            Some(&Some(ci)) => ci,
+            _ => return String::new(),
+        };
+        let name = match operand {
+            Operand::Register(reg) => ci.func.object_name(ci.pc._pc - 1, reg),
+            Operand::Upvalue(index) => Some(("upvalue", ci.func.upvalue_name(index))),
+            Operand::Constant(_) => None,
+        };
+        name.map_or_else(String::new, |(kind, name)| format!(" ({} '{}')", kind, name))
+    }
+
+    /// Like `luaL_traceback`, lists the call stack from `level` on with the current line
+    /// and name of each function. Deep stacks are abbreviated.
+    pub fn traceback(&self, level: usize) -> String {
+        let frames = self.frames();
+        let mut traceback = "stack traceback:".to_owned();
+        let mut i = level;
+        while i < frames.len() {
+            // like the reference implementation, abbreviate if more than one frame would be skipped
+            if i == level + TRACEBACK_HEAD && frames.len() - level > TRACEBACK_HEAD + TRACEBACK_TAIL + 1 {
+                traceback.push_str("\n\t...");
+                i = frames.len() - TRACEBACK_TAIL;
+            }
+            let position = match frames[i] {
+                None => "[C]:".to_owned(),
+                Some(ci) => match ci.current_line() {
+                    Some(line) => format!("{}:{}:", ci.func.short_source(), line),
+                    None => format!("{}:", ci.func.short_source()),
+                },
+            };
+            let caller = frames.get(i + 1).and_then(|&caller| caller);
+            traceback.push_str(&format!("\n\t{} in {}", position, function_name(frames[i], caller)));
+            if frames[i].map_or(false, |ci| ci.tail_call) {
+                traceback.push_str("\n\t(...tail calls...)");
+            }
+            i += 1;
+        }
+        traceback
+    }
+
+    /// Converts any value to a string like `tostring`, honoring `__tostring` and `__name`.
+    pub fn tostring(&mut self, value: &Type) -> LuaResult<LuaString> {
+        match value.metamethod("__tostring") {
+            Type::Nil => {},
+            handler => {
+                return match self.call(handler, vec![value.clone()])?.into_iter().next() {
+                    Some(Type::String(s)) => Ok(s),
+                    _ => Err("'__tostring' must return a string".into()),
+                }
+            },
+        }
+        match (value, value.metamethod("__name")) {
+            (&Type::Table(ref table), Type::String(name)) => Ok(format!("{}: {:p}", name, table.as_ptr()).into()),
+            (&Type::UserData(ref userdata), Type::String(name)) => Ok(format!("{}: {:p}", name, userdata.as_ptr()).into()),
+            (&Type::String(ref s), _) => Ok(s.clone()),
+            _ => Ok(format!("{}", value).into()),
+        }
+    }
+
+    /// Runs a full garbage collection cycle over the objects this context tracks, rooted at the stack,
+    /// the frames, the open upvalues and any reference from outside (see `Heap::collect`),
+    /// then calls the finalizers of the unreachable objects.
+    pub fn collect_garbage(&mut self) -> LuaResult<()> {
+        self.stack.clear_dead_slots();
+        // suspended threads are objects of their own, threads waiting for a resume are held by it
+        let roots = thread_references(&self.stack, &self.call_info, &self.returned, &self.open_upval);
+        let unreachable = self.heap.collect(roots, &mut self.finalizers);
+        self.finalize(unreachable)
+    }
+
+    /// Registers a newly created table, closure, thread or userdata with the cycle collector.
+    /// Values that aren't tracked are only freed by their reference counts.
+    pub fn track(&mut self, value: &Type) {
+        if let Some(object) = Object::from_value(value) {
+            self.heap.track(&object);
+        }
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Marks `value` for finalization if its metatable has a `__gc` field,
+    /// called whenever a metatable is set.
+    pub fn check_finalizer(&mut self, value: &Type) {
+        if value.metamethod("__gc") == Type::Nil {
+            return
+        }
+        if let Some(object) = Object::from_value(value) {
+            if !self.finalizers.contains(&object) {
+                self.finalizers.push(object);
+            }
+        }
+    }
+
+    // Calls `__gc` for each object. Its current metatable decides the finalizer.
+    // All of them run, even if one fails, the first error is reported.
+    fn finalize(&mut self, objects: Vec<Object>) -> LuaResult<()> {
+        let mut result = Ok(());
+        for object in objects {
+            let value = object.to_value();
+            if let handler @ Type::Function(_) = value.metamethod("__gc") {
+                match self.protected_call(handler, vec![value], None) {
+                    Err(ref err) if result.is_ok() => {
+                        result = Err(LuaError::new(format!("error in __gc metamethod ({})", err)))
+                    },
+                    _ => {},
+                }
+            }
+        }
+        result
+    }
+
+    /// Collects garbage if enough objects were created since the last cycle.
+    pub fn check_gc(&mut self) {
+        if self.heap.should_collect() {
+            // like the reference implementation's incremental steps, failing finalizers are ignored
+            let _ = self.collect_garbage();
+        }
+    }
+
+    /// `value[key]`, falling back to `__index` metamethods.
+    /// `operand` tells where the running instruction read `value` from.
+    pub fn index(&mut self, mut value: Type, key: Type, operand: Operand) -> LuaResult<Type> {
+        let mut operand = Some(operand);
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match value {
+                Type::Table(ref table) => {
+                    if let Some(found) = table.lock().get(&key) {
+                        return Ok(found.clone())
+                    }
+                    match value.metamethod("__index") {
+                        Type::Nil => return Ok(Type::Nil),
+                        handler => handler,
+                    }
+                },
+                ref other => match other.metamethod("__index") {
+                    Type::Nil => return Err(self.type_error(other, "index", operand)),
+                    handler => handler,
+                },
+            };
+            if let Type::Function(_) = handler {
+                return Ok(self.call(handler, vec![value, key])?.into_iter().next().unwrap_or(Type::Nil))
+            }
+            value = handler;
+            // further values of the chain come from metatables
+            operand = None;
+        }
+        Err(self.runtime_error("'__index' chain too long; possible loop"))
+    }
+
+    /// `value[key] = new_value`, falling back to `__newindex` metamethods for absent keys.
+    /// `operand` tells where the running instruction read `value` from.
+    pub fn new_index(&mut self, mut value: Type, key: Type, new_value: Type, operand: Operand) -> LuaResult<()> {
+        let mut operand = Some(operand);
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match value {
+                Type::Table(ref table) => {
+                    let present = table.lock().get(&key).is_some();
+                    let handler = if present { Type::Nil } else { value.metamethod("__newindex") };
+                    if handler == Type::Nil {
+                        check_key(&key).map_err(|msg| self.runtime_error(msg))?;
+                        table.lock().insert(key, new_value);
+                        return Ok(())
+                    }
+                    handler
+                },
+                ref other => match other.metamethod("__newindex") {
+                    Type::Nil => return Err(self.type_error(other, "index", operand)),
+                    handler => handler,
+                },
+            };
+            if let Type::Function(_) = handler {
+                self.call(handler, vec![value, key, new_value])?;
+                return Ok(())
+            }
+            value = handler;
+            operand = None;
+        }
+        Err(self.runtime_error("'__newindex' chain too long; possible loop"))
+    }
     
     pub fn ci(&self) -> &CallInfo {
         assert!(!self.call_info.is_empty());
@@ -91,6 +875,34 @@ impl Context {
         &mut self.call_info[index]
     }
 
+    /// Closes the current frame's upvalues, pops it and stores `returns` at its return address.
+    pub fn return_from_frame(&mut self, returns: Vec<Type>) {
+        let call_base = self.stack.get_level(0);
+        self.close_upvalues(call_base);
+        let call_info = self.call_info.pop();
+        self.stack.pop_barrier();
+        match call_info.and_then(|ci| ci.ret) {
+            Some(ret) => ret.store(self, returns),
+            None => self.returned = returns,
+        }
+    }
+
+    /// Pops the frames above `depth` after an error, closing their upvalues.
+    pub fn unwind(&mut self, depth: usize) {
+        while self.call_info.len() > depth {
+            let call_base = self.stack.get_level(0);
+            self.close_upvalues(call_base);
+            self.call_info.pop();
+            self.stack.pop_barrier();
+        }
+    }
+
+    /// Resets the top to the end of the current frame's registers.
+    pub fn reset_top(&mut self) {
+        let frame_size = self.ci().func.stack_size as usize;
+        self.stack.set_top(frame_size);
+    }
+
     pub fn close_upvalues(&mut self, upto: StackLevel) {
         // println!("\nclose_upvalues(upto: {:?})", upto);
         // println!("stack: {}", self.stack.repr());
@@ -128,8 +940,10 @@ impl Context {
         }
         self.open_upval = SharedUpvalue::new(Upvalue::Open {
             position: level,
+            thread: self.thread.clone(),
             next: self.open_upval.clone()
         });
+        self.heap.track(&self.open_upval.as_object());
         self.open_upval.clone()
     }
 }
@@ -155,6 +969,7 @@ impl Interpreter {
         ]);
 
         context.call_info.push(entry_frame);
+        context.reset_top();
         Interpreter {
             context: context,
             bytecode: bytecode,
@@ -166,58 +981,79 @@ impl Interpreter {
         &self.context.ci().pc
     }
 
-    pub fn step(&mut self) {
-        let instruction = *self.pc().current();
-        self.context.ci_mut().pc += 1;
-        instruction.exec(&mut self.context);
+    pub fn step(&mut self) -> LuaResult<()> {
+        self.context.step()
     }
 
     fn print_current_line(&self) {
         let func = &self.context.ci().func;
-        if let Some(ref debug) = func.debug {
-            match func.source_name {
-                Some(ref name) => match debug.line_info.get(self.pc()._pc) {
-                    Some(line) => {
-                        let current = func.lines.0 + *line as usize;
-                        print!(" {}:{};", name, current)
-                    },
-                    None => print!(" {}:?;", name),
-                },
-                None => print!(" @?:?;"),
+        if func.debug.is_some() {
+            match func.line(self.pc()._pc) {
+                Some(line) => print!(" {}:{};", func.short_source(), line),
+                None => print!(" {}:?;", func.short_source()),
             }
         }
     }
 
-    pub fn debug(&mut self) {
+    pub fn debug(&mut self) -> LuaResult<()> {
         print!("pc: {};", self.pc()._pc);
         self.print_current_line();
         println!(" {:?}", self.pc().current());
-        self.step();
+        self.step()?;
         println!("stack: {}", self.context.stack.repr());
         // println!("upval: {:#?}", self.context.ci().upvalues);
         // println!("");
+        Ok(())
     }
 
-    pub fn run(&mut self) -> RunResult {
+    /// Runs the main chunk to completion. On errors, all frames are unwound
+    /// and the error is handed back along with a traceback.
+    pub fn run(&mut self) -> LuaResult<RunResult> {
         let mut result = RunResult {
             instruction_count: 0
         };
         while !self.context.call_info.is_empty() {
-            self.step();
+            if let Err(err) = self.step() {
+                let err = self.context.handle_error(err);
+                self.context.unwind(0);
+                return Err(err)
+            }
             result.instruction_count += 1
         }
-        result
+        Ok(result)
     }
 
-    pub fn run_debug(&mut self) -> RunResult {
+    pub fn run_debug(&mut self) -> LuaResult<RunResult> {
         let mut result = RunResult {
             instruction_count: 0
         };
         while !self.context.call_info.is_empty() {
-            self.debug();
+            if let Err(err) = self.debug() {
+                let err = self.context.handle_error(err);
+                self.context.unwind(0);
+                return Err(err)
+            }
             result.instruction_count += 1
         }
-        result
+        Ok(result)
+    }
+}
+
+impl Drop for Interpreter {
+    // Like closing a Lua state, every pending finalizer runs in reverse order of marking.
+    fn drop(&mut self) {
+        if thread::panicking() {
+            return
+        }
+        // finalizers may mark further objects, errors are ignored while closing
+        loop {
+            let mut pending = mem::replace(&mut self.context.finalizers, vec![]);
+            if pending.is_empty() {
+                break
+            }
+            pending.reverse();
+            let _ = self.context.finalize(pending);
+        }
     }
 }
 
@@ -226,13 +1062,18 @@ impl Interpreter {
 mod tests {
     use super::*;
     use test::Bencher;
-    use bytecode::Bytecode;
+    use bytecode::{Bytecode, bytecode_from};
     use parser::Parsable;
     use env::Environment;
     use std::io::Cursor;
     use std::sync::mpsc;
+    use instructions::*;
+    use types::Number;
+    use function::FunctionInterface;
+    use table::LuaTable;
+    use userdata::{UserData, LightUserData};
 
-    fn interpreter_from_bytes(data: &[u8]) -> (Interpreter, mpsc::Receiver<String>) {
+    fn interpreter_from_bytes(data: &[u8]) -> (Interpreter, mpsc::Receiver<LuaString>) {
         let bytecode = Bytecode::parse(&mut Cursor::new(data.to_vec()));
         let (tx, rx) = mpsc::channel();
         let interpreter = Interpreter::new(bytecode, Environment::Testing(tx));
@@ -242,7 +1083,7 @@ mod tests {
     #[test]
     fn runs_hello_world() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/hello_world"));
-        let result = interpreter.run_debug();
+        let result = interpreter.run_debug().unwrap();
         assert_eq!(result.instruction_count, 4);
         assert_eq!(rx.recv().unwrap(), "Hello, World!");
     }
@@ -250,32 +1091,34 @@ mod tests {
     #[test]
     fn runs_a_bunch_of_constants() {
         let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/a_bunch_of_constants"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
     }
 
-    #[should_panic]
     #[test]
-    fn assert_false_panics() {
+    fn assert_false_fails() {
         let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/assert_false"));
-        interpreter.run_debug();
+        let err = interpreter.run_debug().unwrap_err();
+        assert_eq!(err.value, LuaError::new("assertion failed!").value);
+        assert_eq!(err.traceback.unwrap(), "stack traceback:\n\t[C]: in function 'assert'\n\tassert_false.lua:1: in main chunk");
+        assert!(interpreter.context.call_info.is_empty());
     }
 
     #[test]
     fn runs_assertions() {
         let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/assertions"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
     }
 
     #[test]
     fn runs_table_ops_test() {
         let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/table_ops"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
     }
 
     #[test]
     fn calls_lua_functions() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/function"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
         assert_eq!(rx.recv().unwrap(), "outside a");
         assert_eq!(rx.recv().unwrap(), "inside a");
         assert_eq!(rx.recv().unwrap(), "after a");
@@ -284,7 +1127,7 @@ mod tests {
     #[test]
     fn calculates_gcds_correctly() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/gcd"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
         assert_eq!(rx.recv().unwrap(), "recursive_gcd(99, 56) = 1");
         assert_eq!(rx.recv().unwrap(), "gcd(123, 456) = 3");
     }
@@ -292,7 +1135,7 @@ mod tests {
     #[test]
     fn branches_correctly() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/if_conditions"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
         assert_eq!(rx.recv().unwrap(), "true is truthy");
         assert_eq!(rx.recv().unwrap(), "false is falsey");
     }
@@ -300,7 +1143,7 @@ mod tests {
     #[test]
     fn calculates_n_queens_solution() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/n_queens"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
         let result = vec![
             "|Q|_|_|_|_|_|_|_|",
             "|_|_|_|_|_|_|Q|_|",
@@ -319,7 +1162,7 @@ mod tests {
     #[test]
     fn fib_recursive() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/fib"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
         let values = vec![1, 1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 144, 233, 377, 610, 987];
         for val in &values {
             assert_eq!(rx.recv().unwrap(), format!("{}", val));
@@ -329,16 +1172,673 @@ mod tests {
     #[test]
     fn test_closure() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/closures"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
         assert_eq!(rx.recv().unwrap(), "making add(2)");
         assert_eq!(rx.recv().unwrap(), "add2(5) = ");
         assert_eq!(rx.recv().unwrap(), "7");
     }
 
+    #[test]
+    fn sets_upvalues() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/setupval"));
+        interpreter.run_debug().unwrap();
+        for line in &["1", "2", "1", "3", "2"] {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn runs_generic_for_loops() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/iterators"));
+        interpreter.run_debug().unwrap();
+        for line in &["1 a", "2 b", "3 c", "10"] {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn keeps_binary_strings_intact() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/byte_strings"));
+        interpreter.run_debug().unwrap();
+        let expected = [
+            "4\t1\t2", "true\ttrue", "true\ttrue\ttrue\ttrue", "7\ttrue",
+            "ff\tfe\tnul\tempty\tff", "4", "valid H\u{e9}llo", "\u{20ac}\tstring",
+        ];
+        for line in &expected {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+        let raw = rx.recv().unwrap();
+        assert_eq!(raw.as_bytes(), &b"\xff\x00\xfe\t\xff1"[..]);
+    }
+
+    #[test]
+    fn constructs_tables_with_list_items() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/table_constructors"));
+        interpreter.run_debug().unwrap();
+        for line in &["1", "50", "51", "120"] {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn passes_varargs() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/varargs"));
+        interpreter.run_debug().unwrap();
+        let output = vec![
+            "10",
+            "p\tq",
+            "b\tc",
+            "c",
+            "b\tc",
+            "b\tc",
+            "false\tbad argument #1 to 'select' (index out of range)",
+            "false\tbad argument #1 to 'select' (number has no integer representation)",
+        ];
+        for line in output {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn propagates_multiple_returns() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/multiple_returns"));
+        interpreter.run_debug().unwrap();
+        for line in &["1\t2\t3", "1\t2\t3", "0\t1\t2\t3", "1\t2\t3", "3"] {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn runs_tail_calls_in_constant_space() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/tail_calls"));
+        let mut max_depth = 0;
+        let mut max_stack = 0;
+        while !interpreter.context.call_info.is_empty() {
+            interpreter.step().unwrap();
+            max_depth = max_depth.max(interpreter.context.call_info.len());
+            max_stack = max_stack.max(interpreter.context.stack.size());
+        }
+        assert_eq!(rx.recv().unwrap(), "tail\tcalled");
+        assert_eq!(rx.recv().unwrap(), "1000000");
+        assert!(max_depth <= 3, "call depth grew to {}", max_depth);
+        assert!(max_stack <= 64, "stack grew to {}", max_stack);
+    }
+
+    #[test]
+    fn runs_numeric_for_loops() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/numeric_for"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "done");
+    }
+
+    #[test]
+    fn follows_lua_arithmetic_rules() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/arithmetic"));
+        interpreter.run().unwrap();
+        let expected = [
+            "true\ttrue\t-2",
+            "3.5\t2.0\t1024.0\t1.4142135623731",
+            "3\t-4\t-4\t-4.0\tinf",
+            "2\t1\t-1\t0.5\t-0.5",
+            "-9223372036854775808\t0",
+            "9223372036854775807\t-9223372036854775808\t0\t9223372036854775807\t16",
+            "3\t-6\t-3\t-2.5",
+            "11.0\t32.0\t2.5\t100.0\t10",
+            "1e+15\t1e+100\t0.1\t-0.0\tinf\t-inf\t33.333333333333",
+        ];
+        for line in &expected {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn measures_table_and_string_length() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/length"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "0\t5\t1000\t100\t10000\t2\t5\t0");
+        // strings measure their bytes, whether or not they are valid UTF-8
+        assert_eq!(rx.recv().unwrap(), "2\t3\t2");
+    }
+
+    #[test]
+    fn uses_reference_identity_for_keys() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/reference_keys"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "print\tf\tg\tinner\tnil\ttrue\tfalse\ttrue\ttrue");
+    }
+
+    #[test]
+    fn looks_up_metatable_fields() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/metatables"));
+        interpreter.run().unwrap();
+        let expected = [
+            "25\ttrue",
+            "hello\tnil",
+            "set\tunset?",
+            "3\t2\t3\ta\tnil",
+            "nil\t10",
+            "global undefined_variable",
+            "locked",
+        ];
+        for line in &expected {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn dispatches_arithmetic_metamethods() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/arith_metamethods"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "11,22\t9,18\t3,6\t3,6\t2.5,5.0\t3,6\t1,2\tpow\t-1,-2");
+        assert_eq!(rx.recv().unwrap(), "band\tbor\tbxor\tshl 2\tshr 3\tbnot same\tband\tother\tband");
+    }
+
+    #[test]
+    fn compares_values_like_lua() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/comparisons"));
+        interpreter.run().unwrap();
+        let expected = [
+            "false\ttrue\ttrue\ttrue\ttrue\tfalse\tfalse\tfalse",
+            "true\ttrue\ttrue\ttrue\tfalse",
+            "true\ttrue\ttrue\ttrue\ttrue\ttrue\ttrue",
+            "true\ttrue\ttrue\tfalse\ttrue\tfalse",
+            "true\tfalse\ttrue\tfalse",
+        ];
+        for line in &expected {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+    }
+
+    #[test]
+    fn unwinds_frames_on_errors() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/runtime_error"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(rx.recv().unwrap(), "1");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(err.to_string(), "(error object is a table value)");
+        assert_eq!(interpreter.context.tostring(&err.value).unwrap(), "custom error");
+        assert!(interpreter.context.call_info.is_empty());
+        // the closures still see the values their upvalues held when the error was raised
+        let table = as_type_variant!(interpreter.env.clone(), Type::Table);
+        let inc = table.lock().get(&"inc".into()).cloned().unwrap();
+        let result = interpreter.context.call(inc, vec![]).unwrap();
+        assert_eq!(result, vec![Type::Number(Number::Integer(2))]);
+    }
+
+    #[test]
+    fn raises_and_catches_errors() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/error_handling"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "false\tmsg");
+        assert_eq!(rx.recv().unwrap(), "false\tmsg");
+        assert_eq!(rx.recv().unwrap(), "false\terror_handling.lua:3: msg");
+        assert_eq!(rx.recv().unwrap(), "false\tnil");
+        assert_eq!(rx.recv().unwrap(), "false\t42");
+        assert_eq!(rx.recv().unwrap(), "false\ttrue");
+        assert_eq!(rx.recv().unwrap(), "false\terror_handling.lua:10: in f");
+        assert_eq!(rx.recv().unwrap(), "false\terror_handling.lua:13: in g");
+        assert_eq!(rx.recv().unwrap(), "true\t1\t2\t3");
+        assert_eq!(rx.recv().unwrap(), "true\t3:true:2:true:1:false:error_handling.lua:19: bottom");
+        assert_eq!(rx.recv().unwrap(), "false\terror_handling.lua:26: deep (handled at 'error_handling.lua:26: ')");
+        assert_eq!(rx.recv().unwrap(), "false\terror in error handling");
+        assert_eq!(rx.recv().unwrap(), "true\t3");
+        assert_eq!(rx.recv().unwrap(), "false\thandled: error_handling.lua:38: caught and rethrown");
+        assert_eq!(interpreter.context.stack.size(), 0);
+    }
+
+    #[test]
+    fn prints_tracebacks_for_uncaught_errors() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/traceback"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(rx.recv().unwrap(), "false\tcaught");
+        assert_eq!(err.to_string(), "traceback.lua:3: boom");
+        let traceback: Vec<&str> = vec![
+            "stack traceback:",
+            "\t[C]: in function 'error'",
+            "\ttraceback.lua:3: in field 'field'",
+            "\ttraceback.lua:8: in method 'method'",
+            "\ttraceback.lua:12: in metamethod '__index'",
+            "\ttraceback.lua:17: in upvalue 'up'",
+            "\ttraceback.lua:23: in local 'inner'",
+            "\ttraceback.lua:27: in function 'glob'",
+            "\ttraceback.lua:32: in function <traceback.lua:31>",
+            "\t(...tail calls...)",
+            "\ttraceback.lua:40: in main chunk",
+        ];
+        assert_eq!(err.traceback.unwrap(), traceback.join("\n"));
+    }
+
+    #[test]
+    fn runs_coroutines() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/coroutines"));
+        interpreter.run().unwrap();
+        let expected: Vec<&str> = vec![
+            "sum\t15",
+            "thread\tsuspended",
+            "start\t1\t2",
+            "true\t3",
+            "suspended",
+            "got\t10",
+            "true\t20",
+            "got\t3\t4",
+            "true\tdone\t7",
+            "dead",
+            "false\tcannot resume dead coroutine",
+            "true\t1",
+            "false\tcoroutines.lua:35: oops",
+            "dead",
+            "false\t42",
+            "false\tcoroutines.lua:42: attempt to index a nil value (local 't')",
+            "outer is\tnormal",
+            "inner is\trunning",
+            "yieldable\ttrue",
+            "inner is\tdead",
+            "false\tcannot resume non-suspended coroutine",
+            "thread\ttrue\tfalse",
+            "true\tin pcall",
+            "true\tbottom",
+            "pcall caught\tfalse\tcoroutines.lua:72: after x y+++",
+            "true\tin xpcall",
+            "false\thandled: coroutines.lua:77: again",
+            "true\tdirect",
+            "true\ta\tb",
+            "true\tnested",
+            "3",
+            "true\tfinished",
+            "dead",
+            "own\t100",
+            "own\t101\t101\t1",
+            "1",
+            "iter\t1\tv1",
+            "2",
+            "iter\t2\tv2",
+            "tail",
+            "last",
+            "false\tattempt to yield from outside a coroutine",
+            "false\tcannot resume non-suspended coroutine",
+            "false\tcannot resume dead coroutine",
+            "false\tcoroutines.lua:123: cannot resume dead coroutine",
+            "false\tbad argument #1 to 'coroutine.create' (function expected, got number)",
+            "false\tbad argument #1 to 'coroutine.resume' (thread expected, got table)",
+            "false\tattempt to yield across a C-call boundary",
+            "false\tcoroutines.lua:127: wrapped",
+            "native\tbody",
+            "true",
+            "true\t1\t2",
+            "true\t3",
+            "dead",
+            "true",
+            "nil",
+            "nil",
+        ];
+        let output: Vec<LuaString> = rx.try_iter().collect();
+        assert_eq!(output, expected);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Point { x: i64, y: i64 }
+
+    fn integer_argument(i: &FunctionInterface, index: usize) -> i64 {
+        i.argument(index).to_number().and_then(|n| n.to_integer()).unwrap()
+    }
+
+    // A host function creating a `Point` userdata with the given metatable.
+    fn point_constructor(metatable: LuaTable) -> NativeFunction {
+        Box::new(move |ref mut i| {
+            let userdata = UserData::new(Point { x: integer_argument(i, 0), y: integer_argument(i, 1) });
+            userdata.set_metatable(Some(metatable.clone()));
+            let value = Type::UserData(userdata);
+            i.context().track(&value);
+            i.context().check_finalizer(&value);
+            i.returns(vec![value]);
+            Ok(())
+        })
+    }
+
+    fn point_argument(i: &FunctionInterface, index: usize, function: &str) -> LuaResult<Point> {
+        let userdata = i.check_userdata::<Point>(index, function, "Point")?;
+        Ok(userdata.with(|p: &Point| *p).unwrap())
+    }
+
+    #[test]
+    fn wraps_host_values_in_userdata() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/userdata"));
+        let metatable = LuaTable::new();
+        let methods: Vec<(&str, NativeFunction)> = vec![
+            ("coords", Box::new(|ref mut i| {
+                let p = point_argument(i, 0, "coords")?;
+                i.returns(vec![Type::Number(Number::Integer(p.x)), Type::Number(Number::Integer(p.y))]);
+                Ok(())
+            })),
+            ("move", Box::new(|ref mut i| {
+                let (dx, dy) = (integer_argument(i, 1), integer_argument(i, 2));
+                let userdata = i.check_userdata::<Point>(0, "move", "Point")?;
+                userdata.with_mut(|p: &mut Point| {
+                    p.x += dx;
+                    p.y += dy;
+                }).unwrap();
+                Ok(())
+            })),
+            ("__add", {
+                let constructor = NativeClosure::from(point_constructor(metatable.clone()));
+                Box::new(move |ref mut i| {
+                    let (a, b) = (point_argument(i, 0, "__add")?, point_argument(i, 1, "__add")?);
+                    let args = vec![Type::Number(Number::Integer(a.x + b.x)), Type::Number(Number::Integer(a.y + b.y))];
+                    let returns = i.context().call_native(&constructor, args)?;
+                    i.returns(returns);
+                    Ok(())
+                })
+            }),
+            ("__eq", Box::new(|ref mut i| {
+                let equal = point_argument(i, 0, "__eq")? == point_argument(i, 1, "__eq")?;
+                i.returns(vec![Type::Boolean(equal)]);
+                Ok(())
+            })),
+        ];
+        for (name, method) in methods {
+            let method: Function = method.into();
+            metatable.lock().insert(name.into(), method.into());
+        }
+        metatable.lock().insert("__name".into(), "Point".into());
+
+        let globals: Vec<(&str, NativeFunction)> = vec![
+            ("new_point", point_constructor(metatable.clone())),
+            ("light", Box::new(|ref mut i| {
+                let token = integer_argument(i, 0) as usize;
+                i.returns(vec![Type::LightUserData(LightUserData(token))]);
+                Ok(())
+            })),
+            ("set_uservalue", Box::new(|ref mut i| {
+                let userdata = i.check_userdata::<Point>(0, "set_uservalue", "Point")?;
+                userdata.set_user_value(integer_argument(i, 1) as usize, i.argument(2));
+                Ok(())
+            })),
+            ("get_uservalue", Box::new(|ref mut i| {
+                let userdata = i.check_userdata::<Point>(0, "get_uservalue", "Point")?;
+                i.returns(vec![userdata.user_value(integer_argument(i, 1) as usize)]);
+                Ok(())
+            })),
+        ];
+        let env = as_type_variant!(interpreter.env.clone(), Type::Table);
+        for (name, func) in globals {
+            let func: Function = func.into();
+            env.lock().insert(name.into(), func.into());
+        }
+        env.lock().insert("Point".into(), Type::Table(metatable));
+
+        interpreter.run().unwrap();
+        let expected = [
+            "userdata\ttrue\ttrue\ttrue",
+            "1\t2",
+            "11\t23",
+            "14\t16",
+            "false\ttrue\tfalse",
+            "p\tnil",
+            "false\tbad argument #1 to 'coords' (Point expected, got table)",
+            "false\tbad argument #1 to 'coords' (Point expected, got userdata)",
+            "false\tuserdata.lua:25: attempt to index a nil value (field 'missing')",
+            "false\tuserdata.lua:26: attempt to get length of a Point value (upvalue 'p')",
+            "false\tbad argument #1 to 'setmetatable' (table expected, got userdata)",
+            "userdata\ttrue\tfalse\tnil\tlight",
+            "false\tuserdata.lua:31: attempt to index a userdata value (upvalue 'a')",
+            "kept\tnil",
+            "collected\t0\t0",
+            "nil",
+        ];
+        for line in &expected {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+        assert!(rx.recv().unwrap().to_string().starts_with("Point: 0x"));
+    }
+
+    #[test]
+    fn names_variables_in_error_messages() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/variable_names"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:7: attempt to index a nil value (local 'cfg')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:8: attempt to index a nil value (global 'missing')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:9: attempt to index a nil value (field 'deeper')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:10: attempt to index a nil value (upvalue 'up')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:11: attempt to index a nil value (upvalue 'up')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:12: attempt to index a nil value (local 'cfg')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:13: attempt to call a nil value (global 'undefined')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:14: attempt to call a nil value (field 'method')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:15: attempt to call a nil value (method 'method')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:16: attempt to call a nil value (upvalue 'up')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:17: attempt to perform arithmetic on a table value (local 'a')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:18: attempt to perform arithmetic on a table value (upvalue 't')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:19: attempt to perform arithmetic on a nil value (upvalue 'up')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:20: attempt to perform arithmetic on a nil value (field 'count')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:21: attempt to perform bitwise operation on a string value (local 's')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:22: number (local 'f') has no integer representation");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:23: number has no integer representation");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:24: attempt to concatenate a nil value (upvalue 'up')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:25: attempt to concatenate a nil value (local 'x')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:26: attempt to get length of a nil value (upvalue 'up')");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:27: attempt to compare two table values");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:28: attempt to compare number with string");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:29: 'for' limit must be a number");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:30: attempt to perform 'n%0'");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:31: table index is nil");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:32: attempt to index a number value");
+        assert_eq!(rx.recv().unwrap(), "variable_names.lua:33: attempt to perform arithmetic on a Point value (local 'p')");
+    }
+
+    #[test]
+    fn comparing_tables_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/compare_tables"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "compare_tables.lua:2: attempt to compare two table values");
+    }
+
+    #[test]
+    fn dispatches_call_concat_len_and_tostring_metamethods() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/more_metamethods"));
+        interpreter.run().unwrap();
+        let expected = [
+            "16\t16\t25\t3",
+            "3\t1\t2\t3",
+            "36",
+            "2\ta\tb",
+            "root/usrbin\thome/root\ta1b2.5",
+            "42\t2",
+            "I am named\tI am named\tnil\t1.5\ttrue",
+        ];
+        for line in &expected {
+            assert_eq!(rx.recv().unwrap(), *line);
+        }
+        assert!(rx.recv().unwrap().to_string().starts_with("MyType: 0x"));
+        let line = rx.recv().unwrap().to_string();
+        let mut parts = line.split('\t');
+        assert!(parts.next().unwrap().starts_with("table: 0x"));
+        assert!(parts.next().unwrap().starts_with("function: 0x"));
+    }
+
+    #[test]
+    fn collects_reference_cycles() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/garbage_collection"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "false\ttrue");
+        assert_eq!(rx.recv().unwrap(), "0\ttrue");
+        assert_eq!(rx.recv().unwrap(), "true\ttrue\ttrue\ttrue");
+        assert_eq!(rx.recv().unwrap(), "false\ttrue");
+        assert_eq!(rx.recv().unwrap(), "false\tbad argument #2 to 'collectgarbage' (number has no integer representation)");
+    }
+
+    #[test]
+    fn keeps_collector_state_per_interpreter() {
+        let (mut first, first_rx) = interpreter_from_bytes(include_bytes!("../fixtures/gc_stop"));
+        let (mut second, second_rx) = interpreter_from_bytes(include_bytes!("../fixtures/gc_stop"));
+        first.run().unwrap();
+        second.run().unwrap();
+        for rx in &[first_rx, second_rx] {
+            assert_eq!(rx.recv().unwrap(), "true");
+            assert_eq!(rx.recv().unwrap(), "false");
+        }
+    }
+
+    #[test]
+    fn clears_weak_tables_and_runs_finalizers() {
+        let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/weak_tables"));
+        interpreter.run().unwrap();
+        assert_eq!(rx.recv().unwrap(), "2\ttrue\t1\t2");
+        assert_eq!(rx.recv().unwrap(), "1\ttrue");
+        assert_eq!(rx.recv().unwrap(), "3\tcached\tsecond\tfirst\tfirst\tnil");
+        assert_eq!(rx.recv().unwrap(), "3");
+        assert_eq!(rx.recv().unwrap(), "3");
+        assert!(rx.try_recv().is_err());
+        drop(interpreter);
+        assert_eq!(rx.recv().unwrap(), "closing");
+    }
+
+    #[test]
+    fn protected_metatable_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/protected_metatable"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "cannot change a protected metatable");
+    }
+
+    #[test]
+    fn index_loop_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/index_loop"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "index_loop.lua:3: '__index' chain too long; possible loop");
+    }
+
+    #[test]
+    fn nil_key_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/nil_key"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "nil_key.lua:2: table index is nil");
+    }
+
+    #[test]
+    fn nan_key_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/nan_key"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "nan_key.lua:2: table index is NaN");
+    }
+
+    #[test]
+    fn integer_division_by_zero_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/idiv_zero"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "idiv_zero.lua:2: attempt to perform 'n//0'");
+    }
+
+    #[test]
+    fn integer_modulo_by_zero_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/mod_zero"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "mod_zero.lua:2: attempt to perform 'n%0'");
+    }
+
+    #[test]
+    fn for_zero_step_fails() {
+        let (mut interpreter, _) = interpreter_from_bytes(include_bytes!("../fixtures/for_zero_step"));
+        let err = interpreter.run().unwrap_err();
+        assert_eq!(err.to_string(), "for_zero_step.lua:1: 'for' step is zero");
+    }
+
+    #[test]
+    fn for_loop_rejects_non_numeric_state() {
+        let bytecode = bytecode_from(vec![
+            Instruction::FORLOOP(ForLoop { a: 0, jump: -1 }),
+            Instruction::RETURN(Return { base: 0, count: Count::Known(0) }),
+        ], vec![]);
+        let mut interpreter = Interpreter::new(bytecode, Environment::Empty);
+        interpreter.context.stack.set_top(4);
+        interpreter.context.stack[0] = Type::Number(Number::Float(1.0)).into();
+        interpreter.context.stack[1] = Type::Number(Number::Integer(3)).into();
+        interpreter.context.stack[2] = Type::String("1".into()).into();
+        let err = interpreter.step().unwrap_err();
+        assert_eq!(err.to_string(), "'for' step must be a number");
+    }
+
+    #[test]
+    fn sets_list_batches_from_extra_arg() {
+        let bytecode = bytecode_from(vec![
+            Instruction::NEWTABLE(NewTable { a: 0, array_size: 0, hash_size: 0 }),
+            Instruction::LOADK(LoadK { local: 1, constant: 0 }),
+            Instruction::SETLIST(SetList { a: 0, count: Count::Known(1), batch: 0 }),
+            Instruction::EXTRAARG(ExtraArg { value: 600 }),
+            Instruction::RETURN(Return { base: 0, count: Count::Known(0) }),
+        ], vec![Type::String("far away".into())]);
+        let mut interpreter = Interpreter::new(bytecode, Environment::Empty);
+        for _ in 0..3 {
+            interpreter.step().unwrap();
+        }
+        assert_eq!(interpreter.pc().current(), &Instruction::RETURN(Return { base: 0, count: Count::Known(0) }));
+        let table = as_type_variant!(interpreter.context.stack[0].as_type(), Type::Table);
+        let key = Type::Number(Number::Integer(599 * 50 + 1));
+        assert_eq!(table.lock().get(&key), Some(&Type::String("far away".into())));
+    }
+
+    #[test]
+    fn sets_list_up_to_top_and_resets_it() {
+        let bytecode = bytecode_from(vec![
+            Instruction::NEWTABLE(NewTable { a: 0, array_size: 0, hash_size: 0 }),
+            Instruction::SETLIST(SetList { a: 0, count: Count::Unknown, batch: 1 }),
+            Instruction::RETURN(Return { base: 0, count: Count::Known(0) }),
+        ], vec![]);
+        let mut interpreter = Interpreter::new(bytecode, Environment::Empty);
+        interpreter.step().unwrap();
+        interpreter.context.stack.set_top(3);
+        interpreter.context.stack[1] = Type::Boolean(true).into();
+        interpreter.context.stack[2] = Type::Boolean(false).into();
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.context.stack.top(), 2);
+        let table = as_type_variant!(interpreter.context.stack[0].as_type(), Type::Table);
+        assert_eq!(table.lock().get(&Type::Number(Number::Integer(2))), Some(&Type::Boolean(false)));
+    }
+
+    #[test]
+    fn rejects_missing_and_stray_extra_args() {
+        let bytecode = bytecode_from(vec![
+            Instruction::LOADKX(LoadKx { local: 1 }),
+        ], vec![]);
+        let mut interpreter = Interpreter::new(bytecode, Environment::Empty);
+        let err = interpreter.step().unwrap_err();
+        assert_eq!(err.to_string(), "expected EXTRAARG after LOADKX(LoadKx { local: 1 })");
+
+        let bytecode = bytecode_from(vec![
+            Instruction::EXTRAARG(ExtraArg { value: 0 }),
+        ], vec![]);
+        let mut interpreter = Interpreter::new(bytecode, Environment::Empty);
+        let err = interpreter.step().unwrap_err();
+        assert_eq!(err.to_string(), "EXTRAARG must be consumed by the preceding instruction");
+    }
+
+    #[test]
+    fn set_list_requires_a_table() {
+        let bytecode = bytecode_from(vec![
+            Instruction::SETLIST(SetList { a: 0, count: Count::Known(1), batch: 1 }),
+        ], vec![]);
+        let mut interpreter = Interpreter::new(bytecode, Environment::Empty);
+        interpreter.context.stack[0] = Type::Boolean(true).into();
+        let err = interpreter.step().unwrap_err();
+        assert_eq!(err.to_string(), "SETLIST expects a table, got a boolean value");
+    }
+
+    #[test]
+    fn loads_constants_beyond_bx_range() {
+        let mut constants = vec![Type::Nil; 262144];
+        constants.push(Type::String("beyond Bx".into()));
+        let bytecode = bytecode_from(vec![
+            Instruction::LOADKX(LoadKx { local: 1 }),
+            Instruction::EXTRAARG(ExtraArg { value: 262144 }),
+            Instruction::RETURN(Return { base: 0, count: Count::Known(0) }),
+        ], constants);
+        let mut interpreter = Interpreter::new(bytecode, Environment::Empty);
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.pc()._pc, 2);
+        assert_eq!(interpreter.context.stack[1].as_type(), Type::String("beyond Bx".into()));
+    }
+
     #[test]
     fn fizz_buzz() {
         let (mut interpreter, rx) = interpreter_from_bytes(include_bytes!("../fixtures/fizz_buzz"));
-        interpreter.run_debug();
+        interpreter.run_debug().unwrap();
         let output = vec![
             "1",
             "2",
@@ -7,8 +7,13 @@ extern crate byteorder;
 extern crate parking_lot;
 
 #[macro_use] pub mod types;
+pub mod error;
 pub mod function;
+pub mod string;
 pub mod table;
+pub mod gc;
+pub mod coroutine;
+pub mod userdata;
 
 pub mod interpreter;
 pub mod stack;
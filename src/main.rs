@@ -6,6 +6,7 @@ use lua_interpreter::parser::Parsable;
 
 use std::fs::File;
 use std::io::Cursor;
+use std::process;
 use std::process::Command;
 
 extern crate clap;
@@ -53,9 +54,19 @@ fn main() {
 
     let mut interpreter = Interpreter::new(bytecode, Environment::LuaStandard);
 
-    if matches.is_present("debug") {
-        interpreter.run_debug();
+    let result = if matches.is_present("debug") {
+        interpreter.run_debug()
     } else if !matches.is_present("prettyprint") {
-        interpreter.run();
+        interpreter.run()
+    } else {
+        return
+    };
+    if let Err(err) = result {
+        eprintln!("lua: {}", err);
+        if let Some(ref traceback) = err.traceback {
+            eprintln!("{}", traceback);
+        }
+        drop(interpreter);
+        process::exit(1);
     }
 }
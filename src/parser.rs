@@ -1,6 +1,7 @@
 pub use std::io;
 pub use std::io::Read;
 use byteorder;
+use string::LuaString;
 pub use byteorder::ReadBytesExt;
 
 pub const LUA_SIGNATURE: &'static [u8] = &[0x1B, b'L', b'u', b'a'];
@@ -13,9 +14,10 @@ pub trait Parsable: Sized {
     fn parse<R: Read + Sized>(&mut R) -> Self;
 }
 
+// Debug information like variable names is only ever used as text.
 impl Parsable for String {
     fn parse<R: Read + Sized>(r: &mut R) -> Self {
-        r.parse_lua_string().unwrap()
+        r.parse_lua_string().unwrap().to_str_lossy().into_owned()
     }
 }
 
@@ -78,7 +80,7 @@ pub trait ReadExt: Read + Sized {
         buf
     }
 
-    fn parse_lua_string(&mut self) -> Option<String> {
+    fn parse_lua_string(&mut self) -> Option<LuaString> {
         let len = match self.read_byte() {
             0x00 => return None,
             0xFF => {
@@ -90,7 +92,7 @@ pub trait ReadExt: Read + Sized {
         };
         // println!("string size: {}", len);
         let data = self.read_bytes(len - 1);
-        Some(String::from_utf8_lossy(&data).into_owned())
+        Some(LuaString::new(data))
     }
 }
 
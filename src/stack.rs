@@ -57,6 +57,7 @@ impl From<Type> for StackEntry {
 pub struct Stack {
     _stack: Vec<StackEntry>,
     _closure_base_cache: usize,
+    _top: usize,
 }
 
 impl Stack {
@@ -64,17 +65,47 @@ impl Stack {
         Stack::default()
     }
 
+    /// First free slot above the current frame's values (relative to the frame base).
+    /// This is the logical Lua top, the underlying vector may be larger.
     pub fn top(&self) -> usize {
-        self._stack.len() - 1 - self._closure_base_cache
+        self._top - self._closure_base_cache
+    }
+
+    /// Marks `top` (relative to the frame base) as the end of the current frame's values,
+    /// used by instructions that produce or consume a variable amount of values.
+    pub fn set_top(&mut self, top: usize) {
+        let abs = top + self._closure_base_cache;
+        if self._stack.len() < abs {
+            self._stack.resize(abs, StackEntry::Type(Type::Nil));
+        }
+        self._top = abs;
+    }
+
+    /// Amount of allocated slots across all frames, including barriers.
+    pub fn size(&self) -> usize {
+        self._stack.len()
+    }
+
+    /// Every slot across all frames, including barriers.
+    pub fn entries(&self) -> &[StackEntry] {
+        &self._stack
+    }
+
+    /// Sets the slots above top to nil, so that dead registers don't keep values alive.
+    pub fn clear_dead_slots(&mut self) {
+        for entry in &mut self._stack[self._top..] {
+            *entry = StackEntry::Type(Type::Nil);
+        }
     }
 
     pub fn pop_barrier(&mut self) {
         while let Some(elem) = self._stack.pop() {
             if let StackEntry::ClosureBarrier = elem {
-                self._calc_base();
-                return
+                break
             }
         }
+        self._calc_base();
+        self._top = self._stack.len();
     }
 
     fn _calc_base(&mut self) {
@@ -92,6 +123,7 @@ impl Stack {
     pub fn insert_barrier(&mut self) {
         self._stack.push(StackEntry::ClosureBarrier);
         self._calc_base();
+        self._top = self._stack.len();
     }
 
     pub fn repr(&self) -> String {
@@ -115,6 +147,14 @@ impl Stack {
             })
     }
 
+    pub fn set<T: StackIndex>(&mut self, index: T, value: Type) {
+        let index = index.idx(self._closure_base_cache);
+        match self._stack[index] {
+            StackEntry::ClosureBarrier => panic!("attempted to overwrite a closure barrier at {}", index),
+            ref mut entry => *entry = StackEntry::Type(value),
+        }
+    }
+
     pub fn get_level(&self, index: usize) -> StackLevel {
         StackLevel {
             base: self._closure_base_cache,
@@ -146,7 +186,7 @@ impl IndexMut<usize> for Stack {
     fn index_mut(&mut self, index: usize) -> &mut StackEntry {
         let abs = index + self._closure_base_cache;
         if self._stack.len() <= abs {
-            self._stack.push(StackEntry::Type(Type::Nil))
+            self._stack.resize(abs + 1, StackEntry::Type(Type::Nil))
         }
         assert!(abs < self._stack.len());
         &mut self._stack[abs]
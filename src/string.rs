@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str;
+
+/// A Lua string, i.e. an immutable sequence of arbitrary bytes.
+///
+/// Strings are compared byte-wise, like `strcmp` in the reference implementation.
+/// Most of them hold UTF-8 text, which `as_str` and `to_str_lossy` give access to.
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LuaString (Vec<u8>);
+
+impl LuaString {
+    pub fn new<T: Into<Vec<u8>>>(bytes: T) -> Self {
+        LuaString (bytes.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The string as text, `None` if it isn't valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        str::from_utf8(&self.0).ok()
+    }
+
+    /// The string as text, invalid UTF-8 sequences are replaced by U+FFFD.
+    pub fn to_str_lossy<'a>(&'a self) -> Cow<'a, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    /// Appends the bytes of `other`, used to build up the results of `..`.
+    pub fn push(&mut self, other: &LuaString) {
+        self.0.extend_from_slice(&other.0)
+    }
+}
+
+impl<'a> From<&'a str> for LuaString {
+    fn from(s: &str) -> Self {
+        LuaString (s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for LuaString {
+    fn from(s: String) -> Self {
+        LuaString (s.into_bytes())
+    }
+}
+
+impl<'a> From<&'a [u8]> for LuaString {
+    fn from(bytes: &[u8]) -> Self {
+        LuaString (bytes.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for LuaString {
+    fn from(bytes: Vec<u8>) -> Self {
+        LuaString (bytes)
+    }
+}
+
+impl PartialEq<str> for LuaString {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl<'a> PartialEq<&'a str> for LuaString {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<String> for LuaString {
+    fn eq(&self, other: &String) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+// Meant for messages, the raw bytes are available through `as_bytes`.
+impl fmt::Display for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_str_lossy())
+    }
+}
+
+// Quoted like a `str`, bytes that aren't part of valid UTF-8 are escaped as `\xNN`.
+impl fmt::Debug for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(s) = self.as_str() {
+            return write!(f, "{:?}", s)
+        }
+        write!(f, "\"")?;
+        for &byte in &self.0 {
+            match byte {
+                b'"' => write!(f, "\\\"")?,
+                b'\\' => write!(f, "\\\\")?,
+                printable if printable >= 0x20 && printable < 0x7f => write!(f, "{}", printable as char)?,
+                _ => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_arbitrary_bytes() {
+        let s = LuaString::new(vec![b'a', 0xff, 0x00, b'"']);
+        assert_eq!(s.len(), 4);
+        assert_eq!(s.as_str(), None);
+        assert_eq!(s.to_str_lossy(), "a\u{fffd}\u{0}\"");
+        assert_eq!(format!("{:?}", s), "\"a\\xff\\x00\\\"\"");
+        assert_eq!(format!("{:?}", LuaString::from("täst")), "\"täst\"");
+    }
+
+    #[test]
+    fn compares_bytewise() {
+        assert!(LuaString::from("a") < LuaString::from("b"));
+        assert!(LuaString::from("Z") < LuaString::from("a"));
+        assert!(LuaString::from("ab") < LuaString::new(vec![b'a', 0xff]));
+        assert_eq!(LuaString::from("#"), "#");
+    }
+}
@@ -1,24 +1,323 @@
-use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::hash::{Hash, Hasher};
 use std::fmt;
 use std::cmp;
+use std::mem;
 use types::*;
+use gc::Object;
 use parking_lot::{Mutex, MutexGuard};
 
-pub type LuaTableRaw = BTreeMap<Type, Type>;
+// Integer-valued floats are stored under their integer key, so `t[1]` and `t[1.0]` are the same slot.
+fn normalize_key(key: &Type) -> Type {
+    if let Type::Number(Number::Float(f)) = *key {
+        if let Some(i) = Number::Float(f).to_integer() {
+            return Type::Number(Number::Integer(i))
+        }
+    }
+    key.clone()
+}
+
+// Position of `key` in the array part, if it is an integer in `1..=len`.
+fn array_index(key: &Type, len: usize) -> Option<usize> {
+    match *key {
+        Type::Number(Number::Integer(i)) if i >= 1 && i as u64 <= len as u64 => Some(i as usize - 1),
+        _ => None,
+    }
+}
+
+/// Checks that `key` may be assigned to, looking up a nil or NaN key simply yields nil.
+pub fn check_key(key: &Type) -> Result<(), String> {
+    match *key {
+        Type::Nil => Err("table index is nil".to_owned()),
+        Type::Number(Number::Float(f)) if f.is_nan() => Err("table index is NaN".to_owned()),
+        _ => Ok(()),
+    }
+}
+
+/// Contents of a Lua table: integer keys `1..=n` live in a vector,
+/// everything else in an insertion-ordered hash part.
+///
+/// Assigning `nil` to an existing hash key leaves a tombstone behind instead of removing it,
+/// so `next` keeps working while fields are cleared during a traversal.
+/// Tombstones are dropped once they make up half of the hash part and a new key is inserted.
+#[derive(Debug, Clone, Default)]
+pub struct LuaTableRaw {
+    array: Vec<Type>,
+    entries: Vec<(Type, Type)>,
+    index: HashMap<Type, usize>,
+    dead: usize,
+    metatable: Option<LuaTable>,
+}
+
+impl LuaTableRaw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(array: usize, hash: usize) -> Self {
+        LuaTableRaw {
+            array: Vec::with_capacity(array),
+            entries: Vec::with_capacity(hash),
+            index: HashMap::with_capacity(hash),
+            dead: 0,
+            metatable: None,
+        }
+    }
+
+    pub fn get(&self, key: &Type) -> Option<&Type> {
+        if let Some(i) = array_index(key, self.array.len()) {
+            return Some(&self.array[i]).filter(|value| **value != Type::Nil)
+        }
+        self.get_hash(&normalize_key(key))
+    }
+
+    fn get_hash(&self, key: &Type) -> Option<&Type> {
+        self.index.get(key)
+            .map(|&i| &self.entries[i].1)
+            .filter(|value| **value != Type::Nil)
+    }
+
+    /// Sets `key` to `value`, assigning `nil` clears the field. Returns the previous value.
+    pub fn insert(&mut self, key: Type, value: Type) -> Option<Type> {
+        let key = normalize_key(&key);
+        if let Some(i) = array_index(&key, self.array.len()) {
+            let old = mem::replace(&mut self.array[i], value);
+            return Some(old).filter(|old| *old != Type::Nil)
+        }
+        if array_index(&key, self.array.len() + 1).is_some() && value != Type::Nil {
+            self.array.push(value);
+            self.migrate_to_array();
+            return None
+        }
+        self.insert_hash(key, value)
+    }
+
+    fn insert_hash(&mut self, key: Type, value: Type) -> Option<Type> {
+        if let Some(&i) = self.index.get(&key) {
+            let old = mem::replace(&mut self.entries[i].1, value);
+            match (old == Type::Nil, self.entries[i].1 == Type::Nil) {
+                (false, true) => self.dead += 1,
+                (true, false) => self.dead -= 1,
+                _ => {},
+            }
+            return Some(old).filter(|old| *old != Type::Nil)
+        }
+        if value == Type::Nil {
+            return None
+        }
+        if self.dead * 2 >= self.entries.len() && self.dead > 0 {
+            self.compact();
+        }
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+        None
+    }
+
+    // Moves the keys directly following the array part out of the hash part.
+    fn migrate_to_array(&mut self) {
+        loop {
+            let key = Type::Number(Number::Integer(self.array.len() as i64 + 1));
+            match self.index.get(&key) {
+                Some(&i) if self.entries[i].1 != Type::Nil => {
+                    let value = mem::replace(&mut self.entries[i].1, Type::Nil);
+                    self.dead += 1;
+                    self.array.push(value);
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn compact(&mut self) {
+        let entries = mem::replace(&mut self.entries, vec![]);
+        self.index.clear();
+        for (key, value) in entries {
+            if value != Type::Nil {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        self.dead = 0;
+    }
+
+    pub fn metatable(&self) -> Option<LuaTable> {
+        self.metatable.clone()
+    }
+
+    pub fn set_metatable(&mut self, metatable: Option<LuaTable>) {
+        self.metatable = metatable;
+    }
+
+    /// Every value held by the table, with keys of the hash part listed twice
+    /// (they are stored in the entries and in the index).
+    pub fn references(&self) -> Vec<Type> {
+        let mut references: Vec<Type> = self.array.clone();
+        for &(ref key, ref value) in &self.entries {
+            references.push(key.clone());
+            references.push(key.clone());
+            references.push(value.clone());
+        }
+        if let Some(ref metatable) = self.metatable {
+            references.push(Type::Table(metatable.clone()));
+        }
+        references
+    }
+
+    /// Live entries of both parts, used by the collector to trace weak tables.
+    pub fn pairs(&self) -> Vec<(Type, Type)> {
+        let mut pairs = vec![];
+        let mut key = Type::Nil;
+        while let Ok(Some((k, v))) = self.next(&key) {
+            pairs.push((k.clone(), v));
+            key = k;
+        }
+        pairs
+    }
 
-#[derive(Clone, Default)]
+    /// Clears every entry for which `f(key, value)` holds.
+    pub fn remove_where<F: Fn(&Type, &Type) -> bool>(&mut self, f: F) {
+        for (i, value) in self.array.iter_mut().enumerate() {
+            if *value != Type::Nil && f(&Type::Number(Number::Integer(i as i64 + 1)), value) {
+                *value = Type::Nil;
+            }
+        }
+        for &mut (ref key, ref mut value) in &mut self.entries {
+            if *value != Type::Nil && f(key, value) {
+                *value = Type::Nil;
+                self.dead += 1;
+            }
+        }
+    }
+
+    /// Rough amount of bytes allocated for the array and hash parts.
+    pub fn heap_size(&self) -> usize {
+        let value = mem::size_of::<Type>();
+        self.array.capacity() * value
+            + self.entries.capacity() * 2 * value
+            + self.index.capacity() * (value + mem::size_of::<usize>())
+    }
+
+    /// Returns the entry following `key` in traversal order (`Nil` starts the traversal).
+    /// The array part is traversed first, then the hash part in insertion order.
+    /// Fails for keys that aren't part of the table.
+    pub fn next(&self, key: &Type) -> Result<Option<(Type, Type)>, String> {
+        let key = normalize_key(key);
+        let (array_start, hash_start) = match key {
+            Type::Nil => (0, 0),
+            ref key => match array_index(key, self.array.len()) {
+                Some(i) => (i + 1, 0),
+                None => match self.index.get(key) {
+                    Some(&i) => (self.array.len(), i + 1),
+                    None => return Err("invalid key to 'next'".to_owned()),
+                },
+            },
+        };
+        let array = self.array.iter()
+            .enumerate()
+            .skip(array_start)
+            .find(|&(_, value)| *value != Type::Nil)
+            .map(|(i, value)| (Type::Number(Number::Integer(i as i64 + 1)), value.clone()));
+        Ok(array.or_else(|| {
+            self.entries.iter()
+                .skip(hash_start)
+                .find(|&&(_, ref value)| *value != Type::Nil)
+                .cloned()
+        }))
+    }
+
+    /// Finds a border, an index `n` with `t[n] ~= nil` and `t[n + 1] == nil` (or 0 if `t[1]` is nil).
+    /// Bisects the array part if it ends in `nil`, otherwise continues into the hash part
+    /// by doubling the probed index until it hits a nil value and bisecting from there.
+    pub fn border(&self) -> i64 {
+        let len = self.array.len();
+        if len > 0 && self.array[len - 1] == Type::Nil {
+            let (mut i, mut j) = (0, len);
+            while j - i > 1 {
+                let m = i + (j - i) / 2;
+                if self.array[m - 1] == Type::Nil {
+                    j = m;
+                } else {
+                    i = m;
+                }
+            }
+            return i as i64
+        }
+        let present = |n: i64| self.get_hash(&Type::Number(Number::Integer(n))).is_some();
+        let (mut i, mut j) = (len as i64, len as i64 + 1);
+        while present(j) {
+            i = j;
+            if j > i64::max_value() / 2 {
+                // pathological table, fall back to a linear search
+                let mut n = len as i64;
+                while present(n + 1) {
+                    n += 1;
+                }
+                return n
+            }
+            j *= 2;
+        }
+        while j - i > 1 {
+            let m = i + (j - i) / 2;
+            if present(m) {
+                i = m;
+            } else {
+                j = m;
+            }
+        }
+        i
+    }
+}
+
+#[derive(Clone)]
 pub struct LuaTable (Shared<LuaTableRaw>);
 
 impl LuaTable {
     pub fn new() -> Self {
-        Self::default()
+        LuaTableRaw::new().into()
+    }
+
+    /// Preallocates `array` slots for the sequence and `hash` slots for other keys.
+    pub fn with_capacity(array: usize, hash: usize) -> Self {
+        LuaTableRaw::with_capacity(array, hash).into()
+    }
+
+    /// Address identifying the table, as shown by `tostring`.
+    pub fn as_ptr(&self) -> *const () {
+        &*self.0 as *const Mutex<LuaTableRaw> as *const ()
+    }
+
+    /// Wraps a table that is already known to the collector.
+    pub fn from_shared(shared: Shared<LuaTableRaw>) -> Self {
+        LuaTable (shared)
+    }
+
+    pub fn as_object(&self) -> Object {
+        Object::Table(self.0.clone())
     }
 
     pub fn lock(&self) -> MutexGuard<LuaTableRaw> {
         self.0.lock()
     }
+
+    pub fn metatable(&self) -> Option<LuaTable> {
+        self.lock().metatable()
+    }
+
+    pub fn set_metatable(&self, metatable: Option<LuaTable>) {
+        self.lock().set_metatable(metatable)
+    }
+
+    /// Returns the entry following `key` in traversal order (`Nil` starts the traversal).
+    /// Entries holding `nil` are skipped, so keys may be cleared while iterating.
+    pub fn next(&self, key: &Type) -> Result<Option<(Type, Type)>, String> {
+        self.lock().next(key)
+    }
+
+    /// See `LuaTableRaw::border`.
+    pub fn border(&self) -> i64 {
+        self.lock().border()
+    }
 }
 
 impl From<LuaTableRaw> for LuaTable {
@@ -27,6 +326,7 @@ impl From<LuaTableRaw> for LuaTable {
     }
 }
 
+// Tables are compared and hashed by identity, their contents can't be ordered anyway.
 impl Eq for LuaTable {}
 impl PartialEq for LuaTable {
     fn eq(&self, other: &LuaTable) -> bool {
@@ -36,36 +336,79 @@ impl PartialEq for LuaTable {
 
 impl Ord for LuaTable {
     fn cmp(&self, other: &LuaTable) -> cmp::Ordering {
-        if self == other {
-            cmp::Ordering::Equal
-        } else {
-            let s = self.lock();
-            let o = other.lock();
-            Ord::cmp(&(*s), &(*o))
-        }
+        self.as_ptr().cmp(&other.as_ptr())
     }
 }
 
 impl PartialOrd for LuaTable {
     fn partial_cmp(&self, other: &LuaTable) -> Option<cmp::Ordering> {
-        if self == other {
-            Some(cmp::Ordering::Equal)
-        } else {
-            let s = self.lock();
-            let o = other.lock();
-            PartialOrd::partial_cmp(&(*s), &(*o))
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl Hash for LuaTable {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.lock().hash(state)
+        self.as_ptr().hash(state)
+    }
+}
+
+impl Default for LuaTable {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl fmt::Debug for LuaTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.0)
+        // contents may refer back to the table, e.g. through its metatable
+        write!(f, "table: {:p}", self.as_ptr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(i: i64) -> Type {
+        Type::Number(Number::Integer(i))
+    }
+
+    #[test]
+    fn grows_array_part_from_hash_part() {
+        let mut table = LuaTableRaw::new();
+        for i in (1..11).rev() {
+            table.insert(int(i), int(i * 10));
+        }
+        assert_eq!(table.array.len(), 10);
+        assert_eq!(table.get(&int(7)), Some(&int(70)));
+        assert_eq!(table.border(), 10);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn normalizes_float_keys() {
+        let mut table = LuaTableRaw::new();
+        table.insert(Type::Number(Number::Float(1.0)), "one".into());
+        table.insert(Type::Number(Number::Float(2.5)), "two and a half".into());
+        assert_eq!(table.get(&int(1)), Some(&"one".into()));
+        assert_eq!(table.get(&Type::Number(Number::Float(2.5))), Some(&"two and a half".into()));
+        assert_eq!(table.array.len(), 1);
+    }
+
+    #[test]
+    fn traverses_while_clearing_fields() {
+        let mut table = LuaTableRaw::new();
+        table.insert(int(1), Type::Boolean(true));
+        table.insert("a".into(), int(1));
+        table.insert("b".into(), int(2));
+        table.insert("c".into(), int(3));
+        let mut key = Type::Nil;
+        let mut seen = vec![];
+        while let Some((k, _)) = table.next(&key).unwrap() {
+            table.insert(k.clone(), Type::Nil);
+            seen.push(k.clone());
+            key = k;
+        }
+        assert_eq!(seen, vec![int(1), "a".into(), "b".into(), "c".into()]);
+        assert_eq!(table.next(&Type::Nil), Ok(None));
+    }
+}
@@ -6,6 +6,9 @@ use parking_lot::Mutex;
 use parser::*;
 use function::*;
 use table::*;
+use coroutine::LuaThread;
+use string::LuaString;
+use userdata::{UserData, LightUserData};
 
 pub type Shared<T> = Arc<Mutex<T>>;
 
@@ -24,50 +27,305 @@ impl Into<f64> for Number {
     }
 }
 
+/// Arithmetic and bitwise operators, in the order of their opcodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Pow,
+    Div,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    Unm,
+    BNot,
+}
+
+impl ArithOp {
+    /// Name of the metamethod consulted for non-numeric operands.
+    pub fn event(&self) -> &'static str {
+        match *self {
+            ArithOp::Add => "__add",
+            ArithOp::Sub => "__sub",
+            ArithOp::Mul => "__mul",
+            ArithOp::Mod => "__mod",
+            ArithOp::Pow => "__pow",
+            ArithOp::Div => "__div",
+            ArithOp::IDiv => "__idiv",
+            ArithOp::BAnd => "__band",
+            ArithOp::BOr => "__bor",
+            ArithOp::BXor => "__bxor",
+            ArithOp::Shl => "__shl",
+            ArithOp::Shr => "__shr",
+            ArithOp::Unm => "__unm",
+            ArithOp::BNot => "__bnot",
+        }
+    }
+
+    pub fn is_bitwise(&self) -> bool {
+        match *self {
+            ArithOp::BAnd | ArithOp::BOr | ArithOp::BXor
+            | ArithOp::Shl | ArithOp::Shr | ArithOp::BNot => true,
+            _ => false,
+        }
+    }
+}
+
+fn shift_left(x: i64, y: i64) -> i64 {
+    if y <= -64 || y >= 64 {
+        0
+    } else if y >= 0 {
+        ((x as u64) << y) as i64
+    } else {
+        ((x as u64) >> -y) as i64
+    }
+}
+
+impl Number {
+    /// Converts floats with an exact integer value, fails for anything else.
+    pub fn to_integer(&self) -> Option<i64> {
+        match *self {
+            Number::Integer(i) => Some(i),
+            Number::Float(f) => {
+                if f.floor() == f && f >= -9223372036854775808.0 && f < 9223372036854775808.0 {
+                    Some(f as i64)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Performs `op` with Lua 5.3 semantics. Unary operators ignore `b`.
+    pub fn arith(op: ArithOp, a: Number, b: Number) -> Result<Number, String> {
+        if op.is_bitwise() {
+            let (a, b) = match (a.to_integer(), b.to_integer()) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return Err("number has no integer representation".to_owned()),
+            };
+            return Ok(Number::Integer(match op {
+                ArithOp::BAnd => a & b,
+                ArithOp::BOr => a | b,
+                ArithOp::BXor => a ^ b,
+                ArithOp::Shl => shift_left(a, b),
+                ArithOp::Shr => shift_left(a, b.wrapping_neg()),
+                ArithOp::BNot => !a,
+                _ => unreachable!(),
+            }))
+        }
+        match (op, a, b) {
+            (ArithOp::Div, _, _) | (ArithOp::Pow, _, _) => Ok(Number::float_arith(op, a.into(), b.into())),
+            (_, Number::Integer(a), Number::Integer(b)) => Number::integer_arith(op, a, b),
+            (ArithOp::Unm, Number::Integer(a), _) => Ok(Number::Integer(a.wrapping_neg())),
+            _ => Ok(Number::float_arith(op, a.into(), b.into())),
+        }
+    }
+
+    fn integer_arith(op: ArithOp, a: i64, b: i64) -> Result<Number, String> {
+        Ok(Number::Integer(match op {
+            ArithOp::Add => a.wrapping_add(b),
+            ArithOp::Sub => a.wrapping_sub(b),
+            ArithOp::Mul => a.wrapping_mul(b),
+            ArithOp::Unm => a.wrapping_neg(),
+            ArithOp::Mod => {
+                if b == 0 {
+                    return Err("attempt to perform 'n%0'".to_owned())
+                }
+                let m = a.wrapping_rem(b);
+                if m != 0 && (m ^ b) < 0 { m + b } else { m }
+            },
+            ArithOp::IDiv => {
+                if b == 0 {
+                    return Err("attempt to perform 'n//0'".to_owned())
+                }
+                let q = a.wrapping_div(b);
+                if a.wrapping_rem(b) != 0 && (a ^ b) < 0 { q - 1 } else { q }
+            },
+            _ => unreachable!(),
+        }))
+    }
+
+    fn float_arith(op: ArithOp, a: f64, b: f64) -> Number {
+        Number::Float(match op {
+            ArithOp::Add => a + b,
+            ArithOp::Sub => a - b,
+            ArithOp::Mul => a * b,
+            ArithOp::Div => a / b,
+            ArithOp::Pow => a.powf(b),
+            ArithOp::IDiv => (a / b).floor(),
+            ArithOp::Unm => -a,
+            ArithOp::Mod => {
+                let m = a % b;
+                if (m > 0.0 && b < 0.0) || (m < 0.0 && b > 0.0) { m + b } else { m }
+            },
+            _ => unreachable!(),
+        })
+    }
+}
+
+// Parses a numeric string the way the Lua lexer reads numerals,
+// surrounding whitespace is allowed.
+fn str_to_number(s: &str) -> Option<Number> {
+    let s = s.trim();
+    let (negative, digits) = if s.starts_with('-') {
+        (true, &s[1..])
+    } else if s.starts_with('+') {
+        (false, &s[1..])
+    } else {
+        (false, s)
+    };
+    if digits.starts_with("0x") || digits.starts_with("0X") {
+        return parse_hex(&digits[2..], negative)
+    }
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit() || b == b'.' || b == b'e' || b == b'E' || b == b'-' || b == b'+') {
+        return None
+    }
+    if digits.bytes().all(|b| b.is_ascii_digit()) {
+        if let Ok(i) = s.parse::<i64>() {
+            return Some(Number::Integer(i))
+        }
+    }
+    s.parse::<f64>().ok().map(Number::Float)
+}
+
+fn parse_hex(digits: &str, negative: bool) -> Option<Number> {
+    let (mantissa, exponent) = match digits.find(|c| c == 'p' || c == 'P') {
+        Some(index) => (&digits[..index], Some(&digits[index + 1..])),
+        None => (digits, None),
+    };
+    if mantissa.is_empty() || mantissa == "." {
+        return None
+    }
+    if exponent.is_none() && !mantissa.contains('.') {
+        // hexadecimal integers wrap around
+        let mut value: i64 = 0;
+        for c in mantissa.chars() {
+            value = value.wrapping_mul(16).wrapping_add(c.to_digit(16)? as i64);
+        }
+        return Some(Number::Integer(if negative { value.wrapping_neg() } else { value }))
+    }
+    let mut value = 0.0;
+    let mut scale = 0i32;
+    let mut seen_dot = false;
+    for c in mantissa.chars() {
+        if c == '.' {
+            if seen_dot {
+                return None
+            }
+            seen_dot = true;
+        } else {
+            value = value * 16.0 + c.to_digit(16)? as f64;
+            if seen_dot {
+                scale -= 4;
+            }
+        }
+    }
+    if let Some(exponent) = exponent {
+        scale += exponent.parse::<i32>().ok()?;
+    }
+    let value = value * 2f64.powi(scale);
+    Some(Number::Float(if negative { -value } else { value }))
+}
+
+// Formats floats like Lua's "%.14g", marking integral values with ".0".
+fn format_float(f: f64) -> String {
+    if f.is_infinite() {
+        return if f > 0.0 { "inf".to_owned() } else { "-inf".to_owned() }
+    } else if f.is_nan() {
+        return if f.is_sign_negative() { "-nan".to_owned() } else { "nan".to_owned() }
+    }
+    let precision = 14;
+    let scientific = format!("{:.*e}", precision - 1, f);
+    let exponent_index = scientific.find('e').unwrap();
+    let exponent: i32 = scientific[exponent_index + 1..].parse().unwrap();
+    let formatted = if exponent < -4 || exponent >= precision as i32 {
+        let mantissa = trim_fraction(&scientific[..exponent_index]);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        trim_fraction(&format!("{:.*}", decimals, f)).to_owned()
+    };
+    if formatted.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+        formatted + ".0"
+    } else {
+        formatted
+    }
+}
+
+fn trim_fraction(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Integer(ref i) => write!(f, "{}", i),
+            Number::Float(ref v) => write!(f, "{}", format_float(*v)),
+        }
+    }
+}
+
 impl Eq for Number {}
 impl PartialEq for Number {
     fn eq(&self, other: &Number) -> bool {
-        if let (&Number::Integer(a), &Number::Integer(b)) = (self, other) {
-            a == b
-        } else {
-            let a: f64 = (*self).into();
-            let b: f64 = (*other).into();
-            a == b
-        }
+        self.partial_cmp(other) == Some(::std::cmp::Ordering::Equal)
+    }
+}
+
+// Compares without converting the integer to a float, which would lose precision beyond 2^53.
+fn cmp_int_float(i: i64, f: f64) -> Option<::std::cmp::Ordering> {
+    if f.is_nan() {
+        return None
+    }
+    // the conversion saturates for floats outside of the i128 range, keeping the order intact
+    let floor = f.floor();
+    match (i as i128).cmp(&(floor as i128)) {
+        ::std::cmp::Ordering::Equal if f != floor => Some(::std::cmp::Ordering::Less),
+        order => Some(order),
     }
 }
 
 impl Ord for Number {
     fn cmp(&self, other: &Number) -> ::std::cmp::Ordering {
-        if let (&Number::Integer(a), &Number::Integer(b)) = (self, other) {
-            a.cmp(&b)
-        } else {
-            let a: f64 = (*self).into();
-            let b: f64 = (*other).into();
-            a.partial_cmp(&b).unwrap()
-        }
+        // NaN is unordered, sort it first so that `Type` keeps a total order
+        self.partial_cmp(other).unwrap_or_else(|| {
+            let self_nan = if let Number::Float(f) = *self { f.is_nan() } else { false };
+            if self_nan { ::std::cmp::Ordering::Less } else { ::std::cmp::Ordering::Greater }
+        })
     }
 }
 
 impl PartialOrd for Number {
     fn partial_cmp(&self, other: &Number) -> Option<::std::cmp::Ordering> {
-        if let (&Number::Integer(a), &Number::Integer(b)) = (self, other) {
-            a.partial_cmp(&b)
-        } else {
-            let a: f64 = (*self).into();
-            let b: f64 = (*other).into();
-            a.partial_cmp(&b)
+        match (*self, *other) {
+            (Number::Integer(a), Number::Integer(b)) => a.partial_cmp(&b),
+            (Number::Float(a), Number::Float(b)) => a.partial_cmp(&b),
+            (Number::Integer(a), Number::Float(b)) => cmp_int_float(a, b),
+            (Number::Float(a), Number::Integer(b)) => cmp_int_float(b, a).map(|order| order.reverse()),
         }
     }
 }
 
 impl Hash for Number {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        let val: f64 = match *self {
-            Number::Integer(i) => (i as f64),
-            Number::Float(f) => f,
-        };
-        format!("{}", val).hash(state);
+        // integral floats have to hash like the equal integer
+        match self.to_integer() {
+            Some(i) => i.hash(state),
+            None => {
+                let f: f64 = (*self).into();
+                f.to_bits().hash(state)
+            },
+        }
     }
 }
 
@@ -76,13 +334,12 @@ pub enum Type {
     Nil,
     Boolean(bool),
     Number(Number),
-    String(String),
+    String(LuaString),
     Table(LuaTable),
     Function(Function),
-/*
-    UserData,
-    Thread,
-*/
+    Thread(LuaThread),
+    UserData(UserData),
+    LightUserData(LightUserData),
 }
 
 impl Type {
@@ -94,9 +351,44 @@ impl Type {
             Type::String(_) => "string",
             Type::Table(_) => "table",
             Type::Function(_) => "function",
+            Type::Thread(_) => "thread",
+            Type::UserData(_)
+            | Type::LightUserData(_) => "userdata",
+        }
+    }
+
+    /// The type for error messages like `luaT_objtypename`, a string `__name` in the metatable takes precedence.
+    pub fn type_name(&self) -> String {
+        match self.metamethod("__name") {
+            Type::String(name) => name.to_str_lossy().into_owned(),
+            _ => self.as_type_str().to_owned(),
+        }
+    }
+
+    /// Converts numbers and numeric strings to a `Number`, following Lua's coercion rules.
+    pub fn to_number(&self) -> Option<Number> {
+        match *self {
+            Type::Number(n) => Some(n),
+            Type::String(ref s) => s.as_str().and_then(str_to_number),
+            _ => None,
+        }
+    }
+
+    pub fn metatable(&self) -> Option<LuaTable> {
+        match *self {
+            Type::Table(ref table) => table.metatable(),
+            Type::UserData(ref userdata) => userdata.metatable(),
+            _ => None,
         }
     }
 
+    /// Looks up `event` (e.g. `"__index"`) in the metatable, `Nil` if there is none.
+    pub fn metamethod(&self, event: &str) -> Type {
+        self.metatable()
+            .and_then(|mt| mt.lock().get(&Type::from(event)).cloned())
+            .unwrap_or(Type::Nil)
+    }
+
     pub fn truethy(&self) -> bool {
         match *self {
             Type::Nil => false,
@@ -112,13 +404,12 @@ impl fmt::Display for Type {
             Type::Nil => write!(f, "nil"),
             Type::Boolean(val) => write!(f, "{}", val),
             Type::String(ref val) => write!(f, "{}", val),
-            Type::Number(ref num) => {
-                match *num {
-                    Number::Float(ref v) => write!(f, "{}", v),
-                    Number::Integer(ref v) => write!(f, "{}", v)
-                }
-            },
-            _ => unimplemented!(),
+            Type::Number(ref num) => write!(f, "{}", num),
+            Type::Table(ref table) => write!(f, "table: {:p}", table.as_ptr()),
+            Type::Function(ref func) => write!(f, "function: {:p}", func.as_ptr()),
+            Type::Thread(ref thread) => write!(f, "thread: {:p}", thread.as_ptr()),
+            Type::UserData(ref userdata) => write!(f, "userdata: {:p}", userdata.as_ptr()),
+            Type::LightUserData(ref userdata) => write!(f, "userdata: {:p}", userdata.as_ptr()),
         }
     }
 }
@@ -131,7 +422,10 @@ impl Representable for Type {
             Type::String(ref s) => format!("{:?}", s),
             Type::Number(ref n) => n.repr(),
             Type::Function(ref f) => f.repr(),
-            Type::Table(ref t) => format!("table: {:p}", t),
+            Type::Table(_) => format!("{}", self),
+            Type::Thread(ref t) => t.repr(),
+            Type::UserData(ref u) => u.repr(),
+            Type::LightUserData(ref u) => u.repr(),
             // _ => panic!("repr not implemented for {:?}", self)
         }
     }
@@ -139,10 +433,7 @@ impl Representable for Type {
 
 impl Representable for Number {
     fn repr(&self) -> String {
-        match *self {
-            Number::Integer(ref i) => format!("{}", i),
-            Number::Float(ref f) => format!("{}", f),
-        }
+        format!("{}", self)
     }
 }
 
@@ -163,6 +454,12 @@ impl<'a> From<&'a str> for Type {
     }
 }
 
+impl From<String> for Type {
+    fn from(s: String) -> Self {
+        Type::String(s.into())
+    }
+}
+
 macro_rules! impl_into_type {
     ($from:ty, $variant:path) => (
         impl From<$from> for Type {
@@ -175,8 +472,11 @@ macro_rules! impl_into_type {
 
 impl_into_type!(Number, Type::Number);
 impl_into_type!(LuaTable, Type::Table);
-impl_into_type!(String, Type::String);
+impl_into_type!(LuaString, Type::String);
 impl_into_type!(Function, Type::Function);
+impl_into_type!(LuaThread, Type::Thread);
+impl_into_type!(UserData, Type::UserData);
+impl_into_type!(LightUserData, Type::LightUserData);
 
 pub trait Representable {
     fn repr(&self) -> String;
@@ -2,6 +2,8 @@ use parser::*;
 use stack::StackLevel;
 use interpreter::Context;
 use types::Type;
+use coroutine::LuaThread;
+use gc::Object;
 
 use std::sync::Arc;
 use parking_lot::{Mutex, MutexGuard};
@@ -36,7 +38,8 @@ impl Parsable for UpvalueInfos {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Upvalue {
-    Open { position: StackLevel, next: SharedUpvalue },
+    // `position` is on the stack of `thread`, which may not be the running one
+    Open { position: StackLevel, thread: LuaThread, next: SharedUpvalue },
     Closed(Type),
 }
 
@@ -47,10 +50,17 @@ impl SharedUpvalue {
     pub fn new(uv: Upvalue) -> Self {
         SharedUpvalue(Arc::new(Mutex::new(uv)))
     }
+    pub fn as_object(&self) -> Object {
+        Object::Upvalue(self.0.clone())
+    }
     pub fn value(&self, context: &Context) -> Type {
         let _guard = self.lock();
         _guard.value(context)
     }
+    pub fn set(&self, context: &mut Context, value: Type) {
+        let mut _guard = self.lock();
+        _guard.set(context, value)
+    }
     pub fn next(&self) -> Option<SharedUpvalue> {
         let _guard = self.lock();
         _guard.next()
@@ -69,16 +79,32 @@ impl PartialEq for SharedUpvalue {
 impl Upvalue {
     pub fn value(&self, context: &Context) -> Type {
         // println!("value({:?})", self);
-        let stack = &context.stack;
         match *self {
-            Upvalue::Open { ref position, .. } => {
-                stack.get(*position)
-                    .unwrap_or(Type::Nil)
+            Upvalue::Open { ref position, ref thread, .. } => {
+                let value = if context.is_running(thread) {
+                    context.stack.get(*position)
+                } else {
+                    thread.stack_get(*position)
+                };
+                value.unwrap_or(Type::Nil)
             }
             Upvalue::Closed(ref data) => data.clone()
         }
     }
 
+    pub fn set(&mut self, context: &mut Context, value: Type) {
+        match *self {
+            Upvalue::Open { ref position, ref thread, .. } => {
+                if context.is_running(thread) {
+                    context.stack.set(*position, value)
+                } else {
+                    thread.stack_set(*position, value)
+                }
+            }
+            Upvalue::Closed(ref mut data) => *data = value
+        }
+    }
+
     pub fn next(&self) -> Option<SharedUpvalue> {
         match *self {
             Upvalue::Open { ref next, .. } => {
@@ -0,0 +1,209 @@
+use std::any::Any;
+use std::cmp;
+use std::fmt;
+use std::mem;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use parking_lot::{Mutex, MutexGuard};
+
+use table::LuaTable;
+use types::{Type, Shared, Representable};
+use gc::Object;
+
+pub struct UserDataRaw {
+    value: Box<Any>,
+    metatable: Option<LuaTable>,
+    user_values: Vec<Type>,
+}
+
+impl UserDataRaw {
+    /// The Lua values the userdata refers to, i.e. its metatable and user values.
+    pub fn references(&self) -> Vec<Type> {
+        let mut references: Vec<Type> = self.metatable.iter().cloned().map(Type::Table).collect();
+        references.extend(self.user_values.iter().cloned());
+        references
+    }
+
+    /// Drops the wrapped value, the metatable and the user values.
+    pub fn clear(&mut self) {
+        let contents = (
+            mem::replace(&mut self.value, Box::new(())),
+            self.metatable.take(),
+            mem::replace(&mut self.user_values, vec![]),
+        );
+        drop(contents);
+    }
+}
+
+/// A full userdata, i.e. a host value of any type handed to Lua.
+///
+/// Like tables, userdata are compared by identity and may have a metatable of their own,
+/// which is how scripts get to call methods on them or do arithmetic with them.
+/// Besides the metatable, a userdata can refer to Lua values through its user values.
+#[derive(Clone)]
+pub struct UserData (Shared<UserDataRaw>);
+
+impl UserData {
+    /// Pass the new userdata to `Context::track`, so that cycles through its user values can be collected.
+    pub fn new<T: Any>(value: T) -> Self {
+        UserData (Arc::new(Mutex::new(UserDataRaw {
+            value: Box::new(value),
+            metatable: None,
+            user_values: vec![],
+        })))
+    }
+
+    /// Wraps a userdata that is already known to the collector.
+    pub fn from_shared(shared: Shared<UserDataRaw>) -> Self {
+        UserData (shared)
+    }
+
+    pub fn as_object(&self) -> Object {
+        Object::UserData(self.0.clone())
+    }
+
+    /// Address identifying the userdata, as shown by `tostring`.
+    pub fn as_ptr(&self) -> *const () {
+        &*self.0 as *const Mutex<UserDataRaw> as *const ()
+    }
+
+    pub fn lock<'a>(&'a self) -> MutexGuard<'a, UserDataRaw> {
+        self.0.lock()
+    }
+
+    /// Whether the wrapped value is a `T`.
+    pub fn is<T: Any>(&self) -> bool {
+        self.lock().value.is::<T>()
+    }
+
+    /// Calls `f` with the wrapped value if it is a `T`.
+    /// The userdata stays locked while `f` runs, so `f` mustn't access it again.
+    pub fn with<T: Any, R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.lock().value.downcast_ref::<T>().map(f)
+    }
+
+    /// Like `with`, with mutable access to the wrapped value.
+    pub fn with_mut<T: Any, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+        self.lock().value.downcast_mut::<T>().map(f)
+    }
+
+    pub fn metatable(&self) -> Option<LuaTable> {
+        self.lock().metatable.clone()
+    }
+
+    /// Sets the metatable of this userdata. For a `__gc` metamethod to run,
+    /// pass the userdata to `Context::check_finalizer` afterwards, as `setmetatable` does for tables.
+    pub fn set_metatable(&self, metatable: Option<LuaTable>) {
+        self.lock().metatable = metatable;
+    }
+
+    /// The `n`th user value, `Nil` if it was never set.
+    pub fn user_value(&self, n: usize) -> Type {
+        self.lock().user_values.get(n).cloned().unwrap_or(Type::Nil)
+    }
+
+    pub fn set_user_value(&self, n: usize, value: Type) {
+        let mut raw = self.lock();
+        if raw.user_values.len() <= n {
+            raw.user_values.resize(n + 1, Type::Nil);
+        }
+        raw.user_values[n] = value;
+    }
+}
+
+// Userdata are compared and hashed by identity, like tables.
+impl Eq for UserData {}
+impl PartialEq for UserData {
+    fn eq(&self, other: &UserData) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Ord for UserData {
+    fn cmp(&self, other: &UserData) -> cmp::Ordering {
+        self.as_ptr().cmp(&other.as_ptr())
+    }
+}
+
+impl PartialOrd for UserData {
+    fn partial_cmp(&self, other: &UserData) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for UserData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ptr().hash(state)
+    }
+}
+
+impl fmt::Debug for UserData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "userdata: {:p}", self.as_ptr())
+    }
+}
+
+impl Representable for UserData {
+    fn repr(&self) -> String {
+        format!("userdata: {:p}", self.as_ptr())
+    }
+}
+
+/// A light userdata, an opaque pointer-sized token the host hands to Lua.
+/// Unlike full userdata it is compared by value and can't have a metatable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LightUserData (pub usize);
+
+impl LightUserData {
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        LightUserData (ptr as usize)
+    }
+
+    pub fn as_ptr(&self) -> *const () {
+        self.0 as *const ()
+    }
+}
+
+impl Representable for LightUserData {
+    fn repr(&self) -> String {
+        format!("userdata: {:p}", self.as_ptr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Point { x: i64, y: i64 }
+
+    #[test]
+    fn downcasts_to_the_wrapped_type() {
+        let userdata = UserData::new(Point { x: 1, y: 2 });
+        assert!(userdata.is::<Point>());
+        assert!(!userdata.is::<String>());
+        assert_eq!(userdata.with(|p: &Point| p.x + p.y), Some(3));
+        assert_eq!(userdata.with(|s: &String| s.len()), None);
+        userdata.with_mut(|p: &mut Point| p.x = 10).unwrap();
+        assert_eq!(userdata.with(|p: &Point| p.x), Some(10));
+    }
+
+    #[test]
+    fn compares_by_identity() {
+        let a = UserData::new(Point { x: 1, y: 2 });
+        let b = UserData::new(Point { x: 1, y: 2 });
+        assert_eq!(a, a.clone());
+        assert!(a != b);
+        assert_eq!(LightUserData(8), LightUserData::from_ptr(8 as *const u8));
+    }
+
+    #[test]
+    fn keeps_user_values() {
+        let userdata = UserData::new(());
+        assert_eq!(userdata.user_value(0), Type::Nil);
+        userdata.set_user_value(2, "third".into());
+        assert_eq!(userdata.user_value(1), Type::Nil);
+        assert_eq!(userdata.user_value(2), Type::from("third"));
+        assert_eq!(userdata.lock().references(), vec![Type::Nil, Type::Nil, Type::from("third")]);
+    }
+}